@@ -5,8 +5,8 @@ use std::{
     io::{Read, Write},
 };
 
-use hound::{WavSpec, WavWriter};
-use ncw::NcwReader;
+use hound::{WavReader, WavSpec, WavWriter};
+use ncw::{Compression, NcwReader, NcwWriter};
 
 pub fn main() -> Result<(), Box<dyn Error>> {
     let args: Vec<String> = std::env::args().collect();
@@ -18,7 +18,11 @@ pub fn main() -> Result<(), Box<dyn Error>> {
     let input = File::open(&args[1])?;
     let mut output = File::create(&args[2])?;
 
-    write_wav(&mut NcwReader::read(&input)?, &mut output)?;
+    if args[1].to_lowercase().ends_with(".wav") {
+        write_ncw(WavReader::new(input)?, &mut output)?;
+    } else {
+        write_wav(&mut NcwReader::read(&input)?, &mut output)?;
+    }
 
     Ok(())
 }
@@ -36,20 +40,22 @@ pub fn write_wav<R: Read + Seek, W: Write + Seek>(
 
     let mut writer = WavWriter::new(writer, spec)?;
 
-    let samples = reader.decode_samples()?;
+    let bits_per_sample = reader.header.bits_per_sample;
 
-    for sample in samples {
-        match reader.header.bits_per_sample {
-            32 | 24 => {
-                writer.write_sample(sample)?;
-            }
-            16 => {
-                writer.write_sample(sample as i16)?;
-            }
-            8 => {
-                writer.write_sample(sample as i8)?;
+    for block in reader.blocks() {
+        for sample in block? {
+            match bits_per_sample {
+                32 | 24 => {
+                    writer.write_sample(sample)?;
+                }
+                16 => {
+                    writer.write_sample(sample as i16)?;
+                }
+                8 => {
+                    writer.write_sample(sample as i8)?;
+                }
+                _ => panic!("Unknown output sample format"),
             }
-            _ => panic!("Unknown output sample format"),
         }
     }
     writer.finalize()?;
@@ -57,6 +63,34 @@ pub fn write_wav<R: Read + Seek, W: Write + Seek>(
     Ok(())
 }
 
+/// Encode a wav as NCW the same way Native Instruments' tools do.
+pub fn write_ncw<R: Read, W: Write + Seek>(
+    mut reader: WavReader<R>,
+    writer: &mut W,
+) -> Result<(), Box<dyn Error>> {
+    let spec = reader.spec();
+    let mut writer = NcwWriter::new(
+        writer,
+        spec.channels,
+        spec.bits_per_sample,
+        spec.sample_rate,
+    )?;
+    writer.compression = Compression::NativeInstruments;
+
+    match spec.sample_format {
+        hound::SampleFormat::Int => {
+            let samples = reader.samples::<i32>().collect::<Result<Vec<_>, _>>()?;
+            writer.write_samples(&samples)?;
+        }
+        hound::SampleFormat::Float => {
+            let samples = reader.samples::<f32>().collect::<Result<Vec<_>, _>>()?;
+            writer.write_samples_f32(&samples)?;
+        }
+    }
+
+    Ok(())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -64,7 +98,7 @@ mod tests {
 
     #[test]
     fn test_read_16bit_mono() -> Result<(), Box<dyn Error>> {
-        let file = File::open("test-data/NCW/16-bit.ncw")?;
+        let file = File::open("../../tests/data/16-bit-mono.ncw")?;
         let mut ncw = NcwReader::read(file)?;
         let mut buffer = Cursor::new(Vec::new());
         write_wav(&mut ncw, &mut buffer)?;
@@ -73,10 +107,19 @@ mod tests {
 
     #[test]
     fn test_read_24bit_stereo() -> Result<(), Box<dyn Error>> {
-        let file = File::open("test-data/NCW/24-bit.ncw")?;
+        let file = File::open("../../tests/data/24-bit-stereo.ncw")?;
         let mut ncw = NcwReader::read(file)?;
         let mut buffer = Cursor::new(Vec::new());
         write_wav(&mut ncw, &mut buffer)?;
         Ok(())
     }
+
+    #[test]
+    fn test_write_16bit_stereo() -> Result<(), Box<dyn Error>> {
+        let wav = WavReader::open("../../tests/data/16-bit-stereo.wav")?;
+        let mut buffer = Cursor::new(Vec::new());
+        write_ncw(wav, &mut buffer)?;
+        assert!(buffer.into_inner() == std::fs::read("../../tests/data/16-bit-stereo.ncw")?);
+        Ok(())
+    }
 }
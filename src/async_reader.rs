@@ -0,0 +1,243 @@
+//! Asynchronous reading of NCW files with tokio.
+
+use std::io::{ErrorKind, SeekFrom};
+
+use futures_util::{stream, Stream};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncSeek, AsyncSeekExt};
+
+use crate::decode::{decode_block_frames, BlockHeader, NcwHeader, BLOCK_HEADER_SIZE, HEADER_SIZE};
+
+type Error = crate::NcwError;
+
+/// Asynchronous counterpart of [`NcwReader`](crate::NcwReader) over an `AsyncRead + AsyncSeek`
+/// source. Only reading is asynchronous; blocks are decoded by the same functions as the
+/// synchronous reader.
+#[derive(Debug)]
+pub struct AsyncNcwReader<R> {
+    pub reader: R,
+    pub header: NcwHeader,
+    pub block_offsets: Vec<u32>,
+    pub current_block: usize,
+    /// Same as [`NcwReader::strict_flags`](crate::NcwReader::strict_flags).
+    pub strict_flags: bool,
+    /// Block headers and data of every channel of the block being decoded.
+    payload: Vec<u8>,
+}
+
+impl<R: AsyncRead + AsyncSeek + Unpin> AsyncNcwReader<R> {
+    pub async fn read(mut reader: R) -> Result<Self, Error> {
+        let mut bytes = [0; HEADER_SIZE];
+        read_exact(&mut reader, &mut bytes).await?;
+        let header = NcwHeader::parse(&bytes)?;
+
+        // Read the table in bounded chunks, so a corrupt header cannot size an allocation
+        // beyond what the source actually holds. A short read reports the whole table.
+        let table_len = header.num_blocks()? * 4;
+        let mut block_offsets = Vec::new();
+        let mut chunk = [0; 4096];
+        let mut remaining = table_len;
+        while remaining > 0 {
+            let chunk = &mut chunk[..remaining.min(4096)];
+            read_exact(&mut reader, chunk).await.map_err(|e| match e {
+                Error::ReadError(_) => Error::ReadError(table_len),
+                e => e,
+            })?;
+            block_offsets.extend(
+                chunk
+                    .chunks_exact(4)
+                    .map(|offset| u32::from_le_bytes(offset.try_into().unwrap())),
+            );
+            remaining -= chunk.len();
+        }
+
+        Ok(Self {
+            reader,
+            header,
+            block_offsets,
+            current_block: 0,
+            strict_flags: false,
+            payload: Vec::new(),
+        })
+    }
+
+    /// Decode all blocks into contiguous 32-bit PCM samples.
+    pub async fn decode_samples(&mut self) -> Result<Vec<i32>, Error> {
+        let total_samples = self.header.total_samples(self.block_offsets.len())?;
+        let mut samples = Vec::with_capacity(total_samples);
+
+        for i in 0..self.block_offsets.len() {
+            samples.extend(self.decode_block(i).await?);
+        }
+
+        Ok(samples)
+    }
+
+    /// Decode the block at `current_block` into interleaved 32-bit PCM samples and advance to
+    /// the next block. Returns `None` once every block has been read.
+    pub async fn next_block(&mut self) -> Result<Option<Vec<i32>>, Error> {
+        if self.current_block >= self.block_offsets.len() {
+            return Ok(None);
+        }
+
+        let index = self.current_block;
+        self.current_block += 1;
+
+        self.decode_block(index).await.map(Some)
+    }
+
+    /// Stream of the decoded blocks from `current_block` onwards, as yielded by `next_block`.
+    /// The stream is not `Unpin`, so pin it (e.g. with [`std::pin::pin!`]) to poll it with
+    /// `StreamExt::next`.
+    pub fn blocks(&mut self) -> impl Stream<Item = Result<Vec<i32>, Error>> + '_ {
+        stream::unfold(self, |ncw| async move {
+            let block = ncw.next_block().await.transpose()?;
+            Some((block, ncw))
+        })
+    }
+
+    /// Decode a single block into interleaved samples.
+    async fn decode_block(&mut self, index: usize) -> Result<Vec<i32>, Error> {
+        let start = self.read_block_payload(index).await?;
+        decode_block_frames(
+            &self.payload,
+            &self.header,
+            self.strict_flags,
+            self.header.block_frames(index),
+        )
+        .map_err(|e| e.with_block(index, start))
+    }
+
+    /// Read the block headers and data of every channel of block `index` into `payload`,
+    /// returning the absolute offset of the block.
+    async fn read_block_payload(&mut self, index: usize) -> Result<u64, Error> {
+        let start = self.header.block_start(index, self.block_offsets[index])?;
+        self.reader.seek(SeekFrom::Start(start)).await?;
+
+        self.payload.clear();
+        self.payload.reserve(self.header.max_payload_len());
+        for _ in 0..self.header.channels {
+            let offset = self.payload.len();
+            self.read_channel_payload()
+                .await
+                .map_err(|e| e.in_block(index, start + offset as u64))?;
+        }
+
+        Ok(start)
+    }
+
+    /// Append the block header and data of a single channel to `payload`.
+    async fn read_channel_payload(&mut self) -> Result<(), Error> {
+        let offset = self.payload.len();
+        self.payload.resize(offset + BLOCK_HEADER_SIZE, 0);
+        read_exact(&mut self.reader, &mut self.payload[offset..]).await?;
+        let data_len = BlockHeader::parse(&self.payload[offset..])?
+            .checked_data_len(self.header.bits_per_sample)?;
+
+        let data = self.payload.len();
+        self.payload.resize(data + data_len, 0);
+        read_exact(&mut self.reader, &mut self.payload[data..]).await
+    }
+}
+
+/// Fill `buf`, reporting a short read as [`Error::ReadError`] like the synchronous reader.
+async fn read_exact<R: AsyncRead + Unpin>(reader: &mut R, buf: &mut [u8]) -> Result<(), Error> {
+    match reader.read_exact(buf).await {
+        Ok(_) => Ok(()),
+        Err(e) if e.kind() == ErrorKind::UnexpectedEof => Err(Error::ReadError(buf.len())),
+        Err(e) => Err(e.into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{
+        stereo_with_corrupt_num_samples, unknown_flag_with_extra_flag, STEREO_SECOND_BLOCK,
+        UNKNOWN_FLAG_RIGHT_CHANNEL,
+    };
+    use crate::NcwReader;
+    use futures_util::StreamExt;
+
+    #[tokio::test]
+    async fn test_async_matches_reader() -> Result<(), Error> {
+        for name in [
+            "16-bit-mono",
+            "16-bit-stereo",
+            "24-bit-stereo",
+            "16-bit-stereo-midside",
+            "32-bit-mono-float",
+            "testfile-onezero-16-bit-stereo-multiblock",
+        ] {
+            let path = format!("tests/data/{name}.ncw");
+            let mut ncw = NcwReader::read(std::fs::File::open(&path)?)?;
+            let mut async_ncw = AsyncNcwReader::read(tokio::fs::File::open(&path).await?).await?;
+            assert_eq!(async_ncw.block_offsets, ncw.block_offsets);
+
+            let blocks: Vec<_> = async_ncw.blocks().collect().await;
+            assert_eq!(blocks.len(), ncw.block_offsets.len());
+            for (block, expected) in blocks.into_iter().zip(ncw.blocks()) {
+                assert_eq!(block?, expected?, "{name}");
+            }
+            assert!(async_ncw.next_block().await?.is_none());
+
+            async_ncw.current_block = 0;
+            assert_eq!(async_ncw.decode_samples().await?, ncw.decode_samples()?);
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_async_errors() -> Result<(), Error> {
+        let data = std::fs::read("tests/data/16-bit-stereo.ncw")?;
+
+        assert!(matches!(
+            AsyncNcwReader::read(std::io::Cursor::new(&data[..100])).await,
+            Err(Error::ReadError(HEADER_SIZE))
+        ));
+
+        // A block table claiming nearly 4 GiB is read until the source runs out, and reported
+        // with its full length
+        let mut corrupt = data.clone();
+        corrupt[24..28].copy_from_slice(&u32::MAX.to_le_bytes());
+        let table_len = NcwHeader::parse(&corrupt)?.num_blocks()? * 4;
+        assert!(matches!(
+            AsyncNcwReader::read(std::io::Cursor::new(&corrupt)).await,
+            Err(Error::ReadError(len)) if len == table_len
+        ));
+
+        // Offsets are absolute
+        let mut corrupt = data.clone();
+        corrupt[STEREO_SECOND_BLOCK] = 0;
+        let mut ncw = AsyncNcwReader::read(std::io::Cursor::new(&corrupt)).await?;
+        let mut blocks = std::pin::pin!(ncw.blocks());
+        assert!(blocks.next().await.unwrap().is_ok());
+        assert!(matches!(
+            blocks.next().await.unwrap(),
+            Err(Error::InvalidBlockMagic { block: 1, offset })
+                if offset == STEREO_SECOND_BLOCK as u64
+        ));
+        assert!(blocks.next().await.unwrap().is_ok());
+
+        let mut ncw =
+            AsyncNcwReader::read(std::io::Cursor::new(&data[..STEREO_SECOND_BLOCK + 20])).await?;
+        ncw.current_block = 1;
+        assert!(matches!(
+            ncw.next_block().await,
+            Err(Error::TruncatedBlock { block: 1, offset }) if offset == STEREO_SECOND_BLOCK as u64
+        ));
+
+        let (corrupt, total) = stereo_with_corrupt_num_samples()?;
+        let mut ncw = AsyncNcwReader::read(std::io::Cursor::new(&corrupt)).await?;
+        assert_eq!(ncw.decode_samples().await?.len(), total);
+
+        let data = unknown_flag_with_extra_flag()?;
+        let mut ncw = AsyncNcwReader::read(std::io::Cursor::new(&data)).await?;
+        ncw.strict_flags = true;
+        assert!(matches!(
+            ncw.next_block().await,
+            Err(Error::UnknownBlockFlags { block: 0, offset, flags: 0b110 })
+                if offset == UNKNOWN_FLAG_RIGHT_CHANNEL as u64
+        ));
+        Ok(())
+    }
+}
@@ -0,0 +1,581 @@
+//! Parsing of NCW headers and decoding of blocks from byte slices. Nothing here depends on `std`,
+//! so it can be used on its own in `no_std` builds.
+
+#[cfg(feature = "alloc")]
+use alloc::{vec, vec::Vec};
+
+use crate::unpack::unpack_signed_i32;
+
+type Error = crate::NcwError;
+
+pub(crate) const HEADER_SIZE: usize = 120;
+pub(crate) const BLOCK_HEADER_SIZE: usize = 16;
+pub(crate) const MAX_SAMPLES_PER_BLOCK: usize = 512;
+pub(crate) const BLOCK_MAGIC: u32 = 0x160C9A3E;
+
+#[derive(Debug, Clone)]
+pub struct NcwHeader {
+    pub version: NcwVersion,
+    pub channels: u16,
+    pub bits_per_sample: u16,
+    pub sample_rate: u32,
+    pub num_samples: u32,
+    pub blocks_offset: u32,
+    pub data_offset: u32,
+    pub data_size: u32,
+    /// Set to 1 for files holding 32-bit float samples, 0 for PCM.
+    pub format: u32,
+    /// Remaining header bytes of unknown purpose. Some files store a UTF-16 file name here.
+    pub reserved: [u8; 84],
+}
+
+/// NCW format version, identified by the file magic.
+///
+/// Both versions share the same header layout and block encoding and decode identically; the
+/// version is preserved so files can be rewritten with the magic they were read with.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum NcwVersion {
+    /// Magic `0x01A89ED630010000`.
+    V1,
+    /// Magic `0x01A89ED631010000`, written by current versions of Kontakt.
+    #[default]
+    V2,
+}
+
+impl NcwVersion {
+    pub fn from_magic(magic: u64) -> Option<Self> {
+        match magic {
+            0x01A89ED630010000 => Some(Self::V1),
+            0x01A89ED631010000 => Some(Self::V2),
+            _ => None,
+        }
+    }
+
+    pub fn magic(&self) -> u64 {
+        match self {
+            Self::V1 => 0x01A89ED630010000,
+            Self::V2 => 0x01A89ED631010000,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct BlockHeader {
+    pub base_value: i32,
+    pub bits: i16,
+    pub flags: BlockFlags,
+}
+
+/// Per-block flags stored in the block header.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct BlockFlags(u16);
+
+impl BlockFlags {
+    /// The channel pair is stored as mid/side rather than left/right.
+    pub const MID_SIDE: Self = Self(0b0000000000000001);
+    /// Samples are 32-bit float bit patterns.
+    pub const FLOAT: Self = Self(0b0000000000000010);
+
+    const KNOWN: u16 = Self::MID_SIDE.0 | Self::FLOAT.0;
+
+    pub fn from_bits(bits: u16) -> Self {
+        Self(bits)
+    }
+
+    pub fn bits(&self) -> u16 {
+        self.0
+    }
+
+    pub fn contains(&self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    /// Bits set in the header that have no known meaning.
+    pub fn unknown_bits(&self) -> u16 {
+        self.0 & !Self::KNOWN
+    }
+}
+
+impl core::ops::BitOr for BlockFlags {
+    type Output = Self;
+
+    fn bitor(self, rhs: Self) -> Self {
+        Self(self.0 | rhs.0)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ChannelEncoding {
+    LeftRight,
+    MidSide,
+}
+
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SampleFormat {
+    PCM,
+    Float,
+}
+
+impl BlockHeader {
+    /// Parse the 16-byte block header at the start of `bytes`. An invalid magic is reported at
+    /// offset 0 of block 0.
+    pub fn parse(bytes: &[u8]) -> Result<Self, Error> {
+        let bytes = bytes
+            .get(..BLOCK_HEADER_SIZE)
+            .ok_or(Error::ReadError(BLOCK_HEADER_SIZE))?;
+
+        if u32::from_be_bytes(array(bytes, 0)) != BLOCK_MAGIC {
+            return Err(Error::InvalidBlockMagic {
+                block: 0,
+                offset: 0,
+            });
+        }
+
+        Ok(Self {
+            base_value: i32::from_le_bytes(array(bytes, 4)),
+            bits: i16::from_le_bytes(array(bytes, 8)),
+            flags: BlockFlags::from_bits(u16::from_le_bytes(array(bytes, 10))),
+        })
+    }
+
+    /// Length in bytes of the block data following this header. Compressed blocks store 512
+    /// values of `bits` width, uncompressed blocks store 512 samples of `bits_per_sample`.
+    pub fn data_len(&self, bits_per_sample: u16) -> usize {
+        match self.bits {
+            0 => bits_per_sample as usize / 8 * MAX_SAMPLES_PER_BLOCK,
+            bits => bits.unsigned_abs() as usize * MAX_SAMPLES_PER_BLOCK / 8,
+        }
+    }
+
+    /// Same as `data_len`, but fails for packed widths above 32 bits, so that a corrupt header
+    /// cannot size a read.
+    #[cfg(feature = "std")]
+    pub(crate) fn checked_data_len(&self, bits_per_sample: u16) -> Result<usize, Error> {
+        let bits = self.bits.unsigned_abs();
+        if bits > 32 {
+            return Err(Error::UnsupportedBitDepth(bits));
+        }
+        Ok(self.data_len(bits_per_sample))
+    }
+
+    pub fn channel_encoding(&self) -> ChannelEncoding {
+        if self.flags.contains(BlockFlags::MID_SIDE) {
+            ChannelEncoding::MidSide
+        } else {
+            ChannelEncoding::LeftRight
+        }
+    }
+    pub fn sample_format(&self) -> SampleFormat {
+        if self.flags.contains(BlockFlags::FLOAT) {
+            SampleFormat::Float
+        } else {
+            SampleFormat::PCM
+        }
+    }
+}
+
+impl NcwHeader {
+    /// Parse the 120-byte file header at the start of `bytes`.
+    pub fn parse(bytes: &[u8]) -> Result<Self, Error> {
+        let bytes = bytes
+            .get(..HEADER_SIZE)
+            .ok_or(Error::ReadError(HEADER_SIZE))?;
+
+        let version = NcwVersion::from_magic(u64::from_be_bytes(array(bytes, 0)))
+            .ok_or(Error::InvalidFileSignature)?;
+
+        let header = Self {
+            version,
+            channels: u16::from_le_bytes(array(bytes, 8)),
+            bits_per_sample: u16::from_le_bytes(array(bytes, 10)),
+            sample_rate: u32::from_le_bytes(array(bytes, 12)),
+            num_samples: u32::from_le_bytes(array(bytes, 16)),
+            blocks_offset: u32::from_le_bytes(array(bytes, 20)),
+            data_offset: u32::from_le_bytes(array(bytes, 24)),
+            data_size: u32::from_le_bytes(array(bytes, 28)),
+            format: u32::from_le_bytes(array(bytes, 32)),
+            reserved: array(bytes, 36),
+        };
+
+        if header.channels == 0 {
+            return Err(Error::InvalidChannelCount(header.channels));
+        }
+        if ![8, 16, 24, 32].contains(&header.bits_per_sample) {
+            return Err(Error::UnsupportedBitDepth(header.bits_per_sample));
+        }
+
+        Ok(header)
+    }
+
+    /// Number of blocks in the file. The block offset table between `blocks_offset` and
+    /// `data_offset` holds the offset of every block followed by the end offset.
+    pub fn num_blocks(&self) -> Result<usize, Error> {
+        let block_offsets_len =
+            self.data_offset
+                .checked_sub(self.blocks_offset)
+                .ok_or(Error::InvalidBlockTable {
+                    blocks_offset: self.blocks_offset,
+                    data_offset: self.data_offset,
+                })?;
+
+        Ok((block_offsets_len as usize / 4).saturating_sub(1))
+    }
+
+    /// Number of interleaved samples held by `num_blocks` blocks: `num_samples` frames, capped at
+    /// 512 frames per block so that a corrupt header cannot size an allocation on its own. Fails
+    /// with [`Error::FileTooLarge`] when they cannot be counted in a `usize`.
+    #[cfg(feature = "alloc")]
+    pub(crate) fn total_samples(&self, num_blocks: usize) -> Result<usize, Error> {
+        let frames =
+            (self.num_samples as usize).min(num_blocks.saturating_mul(MAX_SAMPLES_PER_BLOCK));
+        frames
+            .checked_mul(self.channels as usize)
+            .ok_or(Error::FileTooLarge)
+    }
+
+    /// Absolute offset of block `block` from its entry in the block offset table, which must lie
+    /// within the data section.
+    pub(crate) fn block_start(&self, block: usize, block_offset: u32) -> Result<u64, Error> {
+        if block_offset >= self.data_size {
+            return Err(Error::BlockOffsetOutOfRange {
+                block,
+                offset: block_offset,
+            });
+        }
+        Ok(self.data_offset as u64 + block_offset as u64)
+    }
+
+    /// Number of frames held by block `index`, accounting for the partial final block.
+    pub(crate) fn block_frames(&self, index: usize) -> usize {
+        (self.num_samples as usize)
+            .saturating_sub(index * MAX_SAMPLES_PER_BLOCK)
+            .min(MAX_SAMPLES_PER_BLOCK)
+    }
+
+    /// Largest size of the block headers and data of every channel of a block, as read before
+    /// decoding it. Packed widths above 32 bits are rejected, so no channel exceeds 4 bytes per
+    /// sample.
+    #[cfg(feature = "std")]
+    pub(crate) fn max_payload_len(&self) -> usize {
+        (BLOCK_HEADER_SIZE + MAX_SAMPLES_PER_BLOCK * 4) * self.channels as usize
+    }
+
+    pub fn sample_format(&self) -> SampleFormat {
+        if self.format & 1 == 1 {
+            SampleFormat::Float
+        } else {
+            SampleFormat::PCM
+        }
+    }
+}
+
+/// Copy `N` bytes starting at `offset`.
+fn array<const N: usize>(bytes: &[u8], offset: usize) -> [u8; N] {
+    bytes[offset..offset + N].try_into().unwrap()
+}
+
+/// Decode every channel of the block at the start of `data` into `out`, which receives 512
+/// samples for each channel in turn, reconstructing left/right from mid/side pairs. Returns the
+/// number of bytes the block occupies.
+///
+/// With `strict_flags`, blocks setting flags this crate does not understand are rejected.
+/// Errors are reported for block 0, at offsets relative to the start of `data`.
+pub fn decode_block_channels(
+    data: &[u8],
+    header: &NcwHeader,
+    strict_flags: bool,
+    out: &mut [i32],
+) -> Result<usize, Error> {
+    let channels = header.channels as usize;
+    if out.len() < channels * MAX_SAMPLES_PER_BLOCK {
+        return Err(Error::InvalidSampleCount(out.len()));
+    }
+
+    decode_block_with(data, header, strict_flags, |channel, samples, _| {
+        out[channel * MAX_SAMPLES_PER_BLOCK..][..MAX_SAMPLES_PER_BLOCK].copy_from_slice(samples);
+    })
+}
+
+/// Decode every channel of the block at the start of `data`, passing the index, 512 samples and
+/// sample format of each channel to `decoded` once its pair is complete. Returns the number of
+/// bytes the block occupies. Errors are reported as by [`decode_block_channels`].
+pub(crate) fn decode_block_with(
+    data: &[u8],
+    header: &NcwHeader,
+    strict_flags: bool,
+    mut decoded: impl FnMut(usize, &[i32; MAX_SAMPLES_PER_BLOCK], SampleFormat),
+) -> Result<usize, Error> {
+    let channels = header.channels as usize;
+    let bits_per_sample = header.bits_per_sample;
+
+    // Channels are decoded a pair at a time, as mid/side is flagged per channel pair
+    let mut pair = [[0; MAX_SAMPLES_PER_BLOCK]; 2];
+    let mut formats = [SampleFormat::PCM; 2];
+    let mut mid_side = false;
+    let mut offset = 0;
+    for channel in 0..channels {
+        let block_header = decode_channel_at(
+            data,
+            offset,
+            bits_per_sample,
+            strict_flags,
+            &mut pair[channel % 2],
+        )?;
+        offset += BLOCK_HEADER_SIZE + block_header.data_len(bits_per_sample);
+        formats[channel % 2] = block_header.sample_format();
+
+        if channel % 2 == 0 {
+            mid_side = block_header.channel_encoding() == ChannelEncoding::MidSide;
+            if channel + 1 == channels {
+                decoded(channel, &pair[0], formats[0]);
+            }
+        } else {
+            let [mid, side] = &mut pair;
+            if mid_side {
+                decode_mid_side_i32(mid, side);
+            }
+            decoded(channel - 1, mid, formats[0]);
+            decoded(channel, side, formats[1]);
+        }
+    }
+
+    Ok(offset)
+}
+
+/// Write one channel's samples into the interleaved frames of `out`, for as many frames as `out`
+/// holds.
+pub(crate) fn interleave_channel<T>(
+    samples: impl IntoIterator<Item = T>,
+    channel: usize,
+    channels: usize,
+    out: &mut [T],
+) {
+    for (frame, sample) in out.chunks_exact_mut(channels).zip(samples) {
+        frame[channel] = sample;
+    }
+}
+
+/// Parse the channel block header at `offset` in `data` and decode its data into `samples`.
+/// Errors are reported for block 0 at `offset`.
+fn decode_channel_at(
+    data: &[u8],
+    offset: usize,
+    bits_per_sample: u16,
+    strict_flags: bool,
+    samples: &mut [i32],
+) -> Result<BlockHeader, Error> {
+    let block_data = &data[offset.min(data.len())..];
+    let block_header = BlockHeader::parse(block_data).map_err(|e| e.in_block(0, offset as u64))?;
+
+    if strict_flags && block_header.flags.unknown_bits() != 0 {
+        return Err(Error::UnknownBlockFlags {
+            block: 0,
+            offset: offset as u64,
+            flags: block_header.flags.bits(),
+        });
+    }
+
+    decode_channel_block_into(
+        &block_data[BLOCK_HEADER_SIZE..],
+        &block_header,
+        bits_per_sample,
+        samples,
+    )
+    .map_err(|e| e.in_block(0, offset as u64))?;
+
+    Ok(block_header)
+}
+
+/// Decode the block at the start of `data` into 512 interleaved frames.
+#[cfg(feature = "alloc")]
+pub fn decode_block(data: &[u8], header: &NcwHeader) -> Result<Vec<i32>, Error> {
+    decode_block_frames(data, header, false, MAX_SAMPLES_PER_BLOCK)
+}
+
+/// Decode the first `frames` frames of the block at the start of `data` into interleaved
+/// samples. Errors are reported as by [`decode_block_channels`].
+#[cfg(feature = "alloc")]
+pub(crate) fn decode_block_frames(
+    data: &[u8],
+    header: &NcwHeader,
+    strict_flags: bool,
+    frames: usize,
+) -> Result<Vec<i32>, Error> {
+    let channels = header.channels as usize;
+    let mut samples = vec![0; frames * channels];
+    decode_block_with(data, header, strict_flags, |channel, decoded, _| {
+        interleave_channel(decoded.iter().copied(), channel, channels, &mut samples);
+    })?;
+    Ok(samples)
+}
+
+/// Decode the data following a single channel's block header into the first 512 entries of
+/// `samples`.
+pub fn decode_channel_block_into(
+    block_data: &[u8],
+    block_header: &BlockHeader,
+    bits_per_sample: u16,
+    samples: &mut [i32],
+) -> Result<(), Error> {
+    let samples_len = samples.len();
+    let samples = samples
+        .get_mut(..MAX_SAMPLES_PER_BLOCK)
+        .ok_or(Error::InvalidSampleCount(samples_len))?;
+
+    let bits = block_header.bits.unsigned_abs() as usize;
+    if bits > 32 {
+        return Err(Error::UnsupportedBitDepth(bits as u16));
+    }
+
+    let data_len = block_header.data_len(bits_per_sample);
+    let block_data = block_data
+        .get(..data_len)
+        .ok_or(Error::ReadError(data_len))?;
+
+    match block_header.bits.cmp(&0) {
+        core::cmp::Ordering::Greater => {
+            // Delta decode, block_data represents the delta from base_value
+            decode_delta_block_into(block_header.base_value, block_data, bits, samples);
+        }
+        core::cmp::Ordering::Less => {
+            // Bit truncation (simple compression)
+            decode_truncated_block_into(block_data, bits, samples);
+        }
+        core::cmp::Ordering::Equal => {
+            // No compression
+            match bits_per_sample {
+                8 | 16 | 24 | 32 => {
+                    let bytes_per_sample = bits_per_sample as usize / 8;
+                    decode_raw_block_into(block_data, bytes_per_sample, samples);
+                }
+                bits => return Err(Error::UnsupportedBitDepth(bits)),
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Decode a delta block into `samples`, which must hold 512 samples.
+fn decode_delta_block_into(base_sample: i32, deltas: &[u8], bits: usize, samples: &mut [i32]) {
+    debug_assert_eq!(deltas.len(), bits * 64);
+
+    unpack_signed_i32(deltas, bits, samples);
+
+    let mut prev_base = base_sample;
+    for sample in samples.iter_mut() {
+        let delta = *sample;
+        *sample = prev_base;
+        // float blocks are delta encoded on their bit patterns, which may wrap
+        prev_base = prev_base.wrapping_add(delta);
+    }
+}
+
+/// Decode a truncated block into `samples`. Truncated samples are signed.
+fn decode_truncated_block_into(data: &[u8], bit_size: usize, samples: &mut [i32]) {
+    unpack_signed_i32(data, bit_size, samples);
+}
+
+/// Decode an uncompressed block of little-endian samples into `samples`.
+fn decode_raw_block_into(data: &[u8], bytes_per_sample: usize, samples: &mut [i32]) {
+    let shift = 32 - bytes_per_sample * 8;
+
+    for (sample, sample_bytes) in samples.iter_mut().zip(data.chunks_exact(bytes_per_sample)) {
+        let mut temp: u32 = 0;
+        for (i, byte) in sample_bytes.iter().enumerate() {
+            temp |= (*byte as u32) << (i * 8);
+        }
+        // Sign extend from the stored sample width
+        *sample = ((temp << shift) as i32) >> shift;
+    }
+}
+
+/// Reconstruct left/right from a mid/side pair in place, where `left = mid + side` and
+/// `right = mid - side`. This convention has not been confirmed against a mid/side file encoded
+/// by Kontakt, and is likely incomplete: it cannot represent frames where `left + right` is odd.
+fn decode_mid_side_i32(mid: &mut [i32], side: &mut [i32]) {
+    for (m, s) in mid.iter_mut().zip(side.iter_mut()) {
+        let (left, right) = (m.wrapping_add(*s), m.wrapping_sub(*s));
+        *m = left;
+        *s = right;
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+    use crate::NcwReader;
+    use std::fs::File;
+
+    #[test]
+    fn test_decode_from_slice() -> Result<(), Error> {
+        for name in [
+            "16-bit-mono",
+            "24-bit-stereo",
+            "16-bit-stereo-midside",
+            "32-bit-mono-float",
+        ] {
+            let data = std::fs::read(format!("tests/data/{name}.ncw"))?;
+            let header = NcwHeader::parse(&data)?;
+
+            let mut ncw = NcwReader::read(File::open(format!("tests/data/{name}.ncw"))?)?;
+            assert_eq!(format!("{header:?}"), format!("{:?}", ncw.header));
+            assert_eq!(header.num_blocks()?, ncw.block_offsets.len());
+
+            let channels = header.channels as usize;
+            let mut planar = vec![0; channels * MAX_SAMPLES_PER_BLOCK];
+            let block_offsets = ncw.block_offsets.clone();
+            for (offset, expected) in block_offsets.into_iter().zip(ncw.blocks()) {
+                let expected = expected?;
+                let block = &data[(header.data_offset + offset) as usize..];
+
+                let len = decode_block_channels(block, &header, true, &mut planar)?;
+                assert!(len > BLOCK_HEADER_SIZE * channels);
+
+                let samples = decode_block(block, &header)?;
+                assert_eq!(samples.len(), channels * MAX_SAMPLES_PER_BLOCK);
+                assert_eq!(samples[..expected.len()], expected[..]);
+                assert_eq!(planar[0], samples[0]);
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_decode_from_slice_errors() -> Result<(), Error> {
+        let data = std::fs::read("tests/data/16-bit-stereo.ncw")?;
+        let header = NcwHeader::parse(&data)?;
+        let block = &data[header.data_offset as usize..];
+        let mut out = vec![0; 2 * MAX_SAMPLES_PER_BLOCK];
+
+        assert!(matches!(
+            NcwHeader::parse(&data[..100]),
+            Err(Error::ReadError(HEADER_SIZE))
+        ));
+        assert!(matches!(
+            decode_block_channels(block, &header, false, &mut out[..100]),
+            Err(Error::InvalidSampleCount(100))
+        ));
+
+        // Offsets are relative to the start of the block
+        let right = decode_block_channels(&block[..500], &header, false, &mut out);
+        assert!(matches!(
+            right,
+            Err(Error::TruncatedBlock {
+                block: 0,
+                offset: 0
+            })
+        ));
+
+        let len = decode_block_channels(block, &header, false, &mut out)?;
+        let mut corrupt = block[..len].to_vec();
+        let right = BLOCK_HEADER_SIZE + BlockHeader::parse(block)?.data_len(header.bits_per_sample);
+        corrupt[right] = 0;
+        assert!(matches!(
+            decode_block_channels(&corrupt, &header, false, &mut out),
+            Err(Error::InvalidBlockMagic { block: 0, offset }) if offset == right as u64
+        ));
+        Ok(())
+    }
+}
@@ -1,21 +1,145 @@
-use std::{error::Error, fmt::Display};
+#[cfg(feature = "alloc")]
+use alloc::vec::Vec;
+use core::{error::Error, fmt::Display};
 
 #[derive(Debug)]
 pub enum NcwError {
     InvalidFileSignature,
+    InvalidBlockMagic {
+        block: usize,
+        offset: u64,
+    },
+    InvalidBlockTable {
+        blocks_offset: u32,
+        data_offset: u32,
+    },
+    InvalidChannelCount(u16),
+    UnsupportedBitDepth(u16),
+    BlockOffsetOutOfRange {
+        block: usize,
+        offset: u32,
+    },
+    TruncatedBlock {
+        block: usize,
+        offset: u64,
+    },
+    UnknownBlockFlags {
+        block: usize,
+        offset: u64,
+        flags: u16,
+    },
     ReadError(usize),
+    FrameOutOfRange(usize),
+    InvalidSampleCount(usize),
+    SampleOutOfRange(i32),
+    FileTooLarge,
+    #[cfg(feature = "alloc")]
     UTF16Error(Vec<u16>),
+    #[cfg(feature = "std")]
     IoError(std::io::Error),
 }
 
-impl Error for NcwError {}
+impl NcwError {
+    /// Attach the block index and byte offset to an error raised while reading a block.
+    pub(crate) fn in_block(self, block: usize, offset: u64) -> Self {
+        match self {
+            Self::InvalidBlockMagic { .. } => Self::InvalidBlockMagic { block, offset },
+            Self::ReadError(_) => Self::TruncatedBlock { block, offset },
+            e => e,
+        }
+    }
+
+    /// Set the block index of an error raised while decoding a block from a buffer, turning its
+    /// offset within the buffer into an absolute one by adding `base`, the buffer's offset.
+    pub(crate) fn with_block(self, block: usize, base: u64) -> Self {
+        match self {
+            Self::InvalidBlockMagic { offset, .. } => Self::InvalidBlockMagic {
+                block,
+                offset: base + offset,
+            },
+            Self::TruncatedBlock { offset, .. } => Self::TruncatedBlock {
+                block,
+                offset: base + offset,
+            },
+            Self::UnknownBlockFlags { offset, flags, .. } => Self::UnknownBlockFlags {
+                block,
+                offset: base + offset,
+                flags,
+            },
+            e => e,
+        }
+    }
+}
+
+impl Error for NcwError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            #[cfg(feature = "std")]
+            Self::IoError(e) => Some(e),
+            _ => None,
+        }
+    }
+}
 
 impl Display for NcwError {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        f.write_str("Ncw Error: {e}")
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::InvalidFileSignature => write!(f, "Ncw Error: invalid file signature"),
+            Self::InvalidBlockMagic { block, offset } => write!(
+                f,
+                "Ncw Error: invalid magic for block {block} at offset {offset}"
+            ),
+            Self::InvalidBlockTable {
+                blocks_offset,
+                data_offset,
+            } => write!(
+                f,
+                "Ncw Error: block table at {blocks_offset} extends past data offset {data_offset}"
+            ),
+            Self::InvalidChannelCount(channels) => {
+                write!(f, "Ncw Error: invalid channel count {channels}")
+            }
+            Self::UnsupportedBitDepth(bits) => write!(f, "Ncw Error: unsupported bit depth {bits}"),
+            Self::BlockOffsetOutOfRange { block, offset } => write!(
+                f,
+                "Ncw Error: offset {offset} of block {block} is outside the data section"
+            ),
+            Self::TruncatedBlock { block, offset } => {
+                write!(
+                    f,
+                    "Ncw Error: block {block} at offset {offset} is truncated"
+                )
+            }
+            Self::UnknownBlockFlags {
+                block,
+                offset,
+                flags,
+            } => write!(
+                f,
+                "Ncw Error: block {block} at offset {offset} has unknown flags {flags:#06x}"
+            ),
+            Self::ReadError(bytes) => write!(f, "Ncw Error: failed to read {bytes} bytes"),
+            Self::FrameOutOfRange(frame) => write!(f, "Ncw Error: frame {frame} is out of range"),
+            Self::InvalidSampleCount(samples) => write!(
+                f,
+                "Ncw Error: {samples} samples do not divide into whole frames"
+            ),
+            Self::SampleOutOfRange(sample) => {
+                write!(f, "Ncw Error: sample {sample} does not fit the bit depth")
+            }
+            Self::FileTooLarge => write!(
+                f,
+                "Ncw Error: too many samples for the header or for this platform to address"
+            ),
+            #[cfg(feature = "alloc")]
+            Self::UTF16Error(_) => write!(f, "Ncw Error: invalid utf-16 string"),
+            #[cfg(feature = "std")]
+            Self::IoError(e) => write!(f, "Ncw Error: {e}"),
+        }
     }
 }
 
+#[cfg(feature = "std")]
 impl From<std::io::Error> for NcwError {
     fn from(e: std::io::Error) -> Self {
         Self::IoError(e)
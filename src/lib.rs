@@ -1,6 +1,36 @@
+#![cfg_attr(not(feature = "std"), no_std)]
+
+#[cfg(feature = "alloc")]
+extern crate alloc;
+
+#[cfg(feature = "tokio")]
+mod async_reader;
+mod decode;
 mod error;
+#[cfg(feature = "std")]
 mod read_bytes;
+#[cfg(feature = "std")]
 mod reader;
+mod slice;
+#[cfg(all(test, feature = "std"))]
+mod test_util;
+mod unpack;
+#[cfg(feature = "std")]
+mod writer;
 
-pub use self::reader::NcwReader;
+#[cfg(feature = "tokio")]
+pub use self::async_reader::AsyncNcwReader;
+#[cfg(feature = "alloc")]
+pub use self::decode::decode_block;
+pub use self::decode::{
+    decode_block_channels, decode_channel_block_into, BlockFlags, BlockHeader, ChannelEncoding,
+    NcwHeader, NcwVersion, SampleFormat,
+};
+#[cfg(feature = "std")]
+pub use self::reader::{BlockDiagnostic, BlockInfo, BlockInfos, Blocks, NcwReader};
 pub use error::NcwError;
+#[cfg(feature = "memmap2")]
+pub use slice::NcwMmap;
+pub use slice::NcwSlice;
+#[cfg(feature = "std")]
+pub use writer::{Compression, NcwWriter};
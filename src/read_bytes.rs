@@ -48,7 +48,9 @@ pub trait ReadBytesExt: Read + Seek {
     }
 
     fn read_u16_le(&mut self) -> io::Result<u16> {
-        self.read_le_bytes::<u16>()
+        let mut buf = [0u8; 2];
+        self.read_exact(&mut buf)?;
+        Ok(u16::from_le_bytes(buf))
     }
 
     fn read_u8(&mut self) -> io::Result<u8> {
@@ -131,6 +133,12 @@ pub trait ReadBytesExt: Read + Seek {
         Ok(buf)
     }
 
+    /// Fill `buf` from the reader without allocating (failable)
+    fn read_bytes_into(&mut self, buf: &mut [u8]) -> Result<(), Error> {
+        self.read_exact(buf)
+            .map_err(|_| Error::ReadError(buf.len()))
+    }
+
     fn read_string_utf8(&mut self) -> io::Result<String> {
         let mut bytes = Vec::new();
         loop {
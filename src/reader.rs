@@ -1,48 +1,68 @@
-use std::io::{Cursor, Read, Seek, SeekFrom};
-
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::ops::Range;
+
+use crate::decode::{
+    decode_block_channels, decode_block_frames, decode_block_with, decode_channel_block_into,
+    interleave_channel, BlockHeader, ChannelEncoding, NcwHeader, SampleFormat, BLOCK_HEADER_SIZE,
+    BLOCK_MAGIC, HEADER_SIZE, MAX_SAMPLES_PER_BLOCK,
+};
 use crate::read_bytes::ReadBytesExt;
 
 type Error = crate::NcwError;
 
-const HEADER_SIZE: usize = 120;
-const BLOCK_HEADER_SIZE: usize = 16;
-const MAX_SAMPLES_PER_BLOCK: usize = 512;
-// const MAX_CHANNELS: usize = 6;
-
 #[derive(Debug)]
 pub struct NcwReader<R> {
     pub reader: R,
     pub header: NcwHeader,
     pub block_offsets: Vec<u32>,
+    /// Block read next by `next_block`, `decode_block_into` and `read_frames_into`. Changing it
+    /// discards any frames `read_frames_into` has buffered but not yet returned.
     pub current_block: usize,
+    /// Fail with [`Error::UnknownBlockFlags`] when a block sets flags this crate does not
+    /// understand, instead of decoding it as if they were clear.
+    pub strict_flags: bool,
+    /// Block headers and data of every channel of the block being decoded, sized by
+    /// `reserve_buffers` or grown on first use.
+    payload: Vec<u8>,
+    /// Planar samples of the last block read by `read_frames_into` or `seek_to_frame`, sized by
+    /// `reserve_buffers` or allocated on first use.
+    scratch: Vec<i32>,
+    /// Frames of `scratch` not yet returned by `read_frames_into`.
+    buffered: Range<usize>,
+    /// Value of `current_block` after the block in `scratch` was read, so `buffered` can be
+    /// discarded once `current_block` has been changed.
+    buffered_next: usize,
 }
 
+/// A block that could not be decoded and was replaced with silence.
 #[derive(Debug)]
-pub struct NcwHeader {
-    pub channels: u16,
-    pub bits_per_sample: u16,
-    pub sample_rate: u32,
-    pub num_samples: u32,
-    pub blocks_offset: u32,
-    pub data_offset: u32,
-    pub data_size: u32,
+pub struct BlockDiagnostic {
+    pub block: usize,
+    pub offset: u64,
+    pub error: Error,
 }
 
+/// Location and header of a single channel of a block, as yielded by
+/// [`NcwReader::block_infos`].
 #[derive(Debug)]
-pub struct BlockHeader {
-    pub base_value: i32,
-    pub bits: i16,
-    pub flags: u16,
+pub struct BlockInfo {
+    pub block: usize,
+    pub channel: usize,
+    /// Absolute byte offset of the block header.
+    pub offset: u64,
+    pub header: BlockHeader,
+    /// Length in bytes of the block data following the header.
+    pub data_len: usize,
+    pub channel_encoding: ChannelEncoding,
+    pub sample_format: SampleFormat,
 }
 
 impl<R: Read + Seek> NcwReader<R> {
     pub fn read(mut reader: R) -> Result<Self, Error> {
         let header = NcwHeader::read(&mut reader)?;
-        let block_offsets_len = header.data_offset - header.blocks_offset;
-        let num_blocks = block_offsets_len / 4;
 
         let mut block_offsets = Vec::new();
-        for _ in 1..num_blocks {
+        for _ in 0..header.num_blocks()? {
             block_offsets.push(reader.read_u32_le()?);
         }
 
@@ -51,267 +71,535 @@ impl<R: Read + Seek> NcwReader<R> {
             header,
             block_offsets,
             current_block: 0,
+            strict_flags: false,
+            payload: Vec::new(),
+            scratch: Vec::new(),
+            buffered: 0..0,
+            buffered_next: 0,
         })
     }
 
-    // pub fn read_f32_block(&self, block_data: &Vec<u8>, block_header: &BlockHeader) -> Vec<f32> {}
+    /// Size the buffers used to decode a block for the largest block the header allows, so that
+    /// `decode_block_into`, `read_frames_into` and `seek_to_frame` never allocate for them.
+    /// Without this, their first call allocates. `read` does not size them itself, as a header
+    /// may claim up to 65535 channels.
+    pub fn reserve_buffers(&mut self) {
+        let channels = self.header.channels as usize;
+        self.payload.clear();
+        self.payload.reserve(self.header.max_payload_len());
+        if self.scratch.is_empty() {
+            self.scratch = vec![0; MAX_SAMPLES_PER_BLOCK * channels];
+        }
+    }
 
-    pub fn read_i32_block(&self, block_data: &Vec<u8>, block_header: &BlockHeader) -> Vec<i32> {
-        dbg!(block_header.channel_encoding());
-        dbg!(block_header.sample_format());
+    pub fn read_i32_block(
+        &self,
+        block_data: &[u8],
+        block_header: &BlockHeader,
+    ) -> Result<Vec<i32>, Error> {
+        let mut samples = vec![0; MAX_SAMPLES_PER_BLOCK];
+        decode_channel_block_into(
+            block_data,
+            block_header,
+            self.header.bits_per_sample,
+            &mut samples,
+        )?;
+        Ok(samples)
+    }
 
-        let bits = block_header.bits.unsigned_abs() as usize;
+    /// Decode all blocks into contiguous 32-bit PCM samples.
+    pub fn decode_samples(&mut self) -> Result<Vec<i32>, Error> {
+        let total_samples = self.header.total_samples(self.block_offsets.len())?;
+        let mut samples = Vec::with_capacity(total_samples);
 
-        match block_header.bits.cmp(&0) {
-            std::cmp::Ordering::Greater => {
-                // Delta decode, block_data represents the delta from base_value
-                decode_delta_block_i32(block_header.base_value, &block_data, bits)
-            }
-            std::cmp::Ordering::Less => {
-                // Bit truncation (simple compression)
-                let bits = block_header.bits.unsigned_abs() as usize;
-                decode_truncated_block_i32(&block_data, bits)
-            }
-            std::cmp::Ordering::Equal => {
-                // No compression
-                let bytes_per_sample = self.header.bits_per_sample as usize / 8;
-                let mut samples = Vec::new();
+        for i in 0..self.block_offsets.len() {
+            samples.extend(self.decode_block(i)?);
+        }
 
-                let mut reader = Cursor::new(block_data);
+        Ok(samples)
+    }
 
-                for _ in 0..512 {
-                    let sample_bytes = reader.read_bytes(bytes_per_sample).unwrap();
-                    let sample = i32::from_le_bytes(sample_bytes.try_into().unwrap());
-                    samples.push(sample);
-                }
+    /// Same as `decode_samples`, decoding blocks in parallel once their payloads have been read.
+    /// The result is identical.
+    #[cfg(feature = "rayon")]
+    pub fn decode_samples_parallel(&mut self) -> Result<Vec<i32>, Error> {
+        use rayon::prelude::*;
+
+        let blocks = (0..self.block_offsets.len())
+            .map(|i| {
+                let start = self.read_block_payload(i)?;
+                Ok((start, self.payload.clone(), self.header.block_frames(i)))
+            })
+            .collect::<Result<Vec<_>, Error>>()?;
+
+        let (header, strict_flags) = (&self.header, self.strict_flags);
+        let blocks = blocks
+            .into_par_iter()
+            .enumerate()
+            .map(|(i, (start, payload, frames))| {
+                decode_block_frames(&payload, header, strict_flags, frames)
+                    .map_err(|e| e.with_block(i, start))
+            })
+            .collect::<Result<Vec<_>, Error>>()?;
+
+        Ok(blocks.concat())
+    }
 
-                samples
+    /// Decode all blocks into contiguous 32-bit PCM samples, substituting silence for blocks with
+    /// an invalid magic, an out of range offset, truncated data or (with `strict_flags`) unknown
+    /// flags instead of failing. Every
+    /// skipped block is reported in the returned diagnostics.
+    pub fn decode_samples_lenient(&mut self) -> Result<(Vec<i32>, Vec<BlockDiagnostic>), Error> {
+        let channels = self.header.channels as usize;
+        let mut samples = Vec::with_capacity(self.header.total_samples(self.block_offsets.len())?);
+        let mut diagnostics = Vec::new();
+
+        for i in 0..self.block_offsets.len() {
+            match self.decode_block(i) {
+                Ok(block) => samples.extend(block),
+                Err(
+                    error @ (Error::InvalidBlockMagic { .. }
+                    | Error::BlockOffsetOutOfRange { .. }
+                    | Error::TruncatedBlock { .. }
+                    | Error::UnknownBlockFlags { .. }
+                    | Error::UnsupportedBitDepth(_)),
+                ) => {
+                    samples.resize(samples.len() + self.header.block_frames(i) * channels, 0);
+                    diagnostics.push(BlockDiagnostic {
+                        block: i,
+                        offset: self.header.data_offset as u64 + self.block_offsets[i] as u64,
+                        error,
+                    });
+                }
+                Err(e) => return Err(e),
             }
         }
+
+        Ok((samples, diagnostics))
     }
 
-    /// Decode all blocks into contiguous 32-bit PCM samples.
-    pub fn decode_samples(&mut self) -> Result<Vec<i32>, Error> {
-        let total_samples = self.header.num_samples as usize * self.header.channels as usize;
-        // let mut samples = Vec::new();
+    /// Decode all blocks into contiguous 32-bit float samples. Float blocks are reinterpreted
+    /// bit-exactly, while PCM blocks are normalised to `-1.0..1.0` by `bits_per_sample`.
+    pub fn decode_samples_f32(&mut self) -> Result<Vec<f32>, Error> {
+        let total_samples = self.header.total_samples(self.block_offsets.len())?;
+        let mut samples = Vec::with_capacity(total_samples);
 
-        let mut channels = vec![Vec::new(); self.header.channels as usize];
+        for i in 0..self.block_offsets.len() {
+            samples.extend(self.decode_block_f32(i)?);
+        }
 
-        let overflow_samples =
-            (total_samples % MAX_SAMPLES_PER_BLOCK) / self.header.channels as usize;
+        Ok(samples)
+    }
 
-        for i in 0..self.block_offsets.len() {
-            let is_final_block: bool = i == self.block_offsets.len() - 1;
+    /// Decode `len` frames starting at `start_frame` into interleaved 32-bit PCM samples,
+    /// reading only the blocks that cover the range.
+    pub fn decode_range(&mut self, start_frame: usize, len: usize) -> Result<Vec<i32>, Error> {
+        let end_frame = start_frame.saturating_add(len);
+        if end_frame > self.header.num_samples as usize {
+            return Err(Error::FrameOutOfRange(end_frame));
+        }
 
-            // Seek to current block
-            self.reader.seek(SeekFrom::Start(
-                self.header.data_offset as u64 + self.block_offsets[i] as u64,
-            ))?;
+        let channels = self.header.channels as usize;
+        let mut samples = Vec::with_capacity(len * channels);
+        if len == 0 {
+            return Ok(samples);
+        }
 
-            for c in 0..self.header.channels as usize {
-                let block_header = BlockHeader::read(&mut self.reader)?;
+        let first_block = start_frame / MAX_SAMPLES_PER_BLOCK;
+        let last_block = (end_frame - 1) / MAX_SAMPLES_PER_BLOCK;
+        // A corrupt header may claim more frames than the block table holds
+        if last_block >= self.block_offsets.len() {
+            return Err(Error::FrameOutOfRange(end_frame));
+        }
+
+        for index in first_block..=last_block {
+            let block = self.decode_block(index)?;
+            let block_start = index * MAX_SAMPLES_PER_BLOCK;
+
+            let from = start_frame.max(block_start) - block_start;
+            let to = end_frame.min(block_start + MAX_SAMPLES_PER_BLOCK) - block_start;
+
+            samples.extend_from_slice(&block[from * channels..to * channels]);
+        }
 
-                let bits = block_header.bits.unsigned_abs();
-                let data = self.reader.read_bytes(bits as usize * 64)?;
+        Ok(samples)
+    }
 
-                let mut current_sample = 0;
-                for sample in self.read_i32_block(&data.clone(), &block_header) {
-                    let is_final_sample: bool = current_sample >= overflow_samples;
+    /// Decode the block at `current_block` into interleaved 32-bit PCM samples and advance to
+    /// the next block. Returns `None` once every block has been read.
+    pub fn next_block(&mut self) -> Result<Option<Vec<i32>>, Error> {
+        if self.current_block >= self.block_offsets.len() {
+            return Ok(None);
+        }
 
-                    current_sample += 1;
+        let index = self.current_block;
+        self.current_block += 1;
+        self.buffered = 0..0;
 
-                    // if we are on the final block
-                    if is_final_block && is_final_sample {
-                    } else {
-                        // samples.push(sample);
-                        channels[c].push(sample);
-                    }
+        self.decode_block(index).map(Some)
+    }
+
+    /// Decode the block at `current_block` into `out` as interleaved 32-bit PCM samples and
+    /// advance to the next block. Returns the number of frames written, which is 0 once every
+    /// block has been read. `out` must hold the whole block, i.e. 512 frames for all but the
+    /// final block.
+    ///
+    /// Nothing is allocated after `reserve_buffers`. Frames buffered by `read_frames_into` but
+    /// not yet returned are discarded.
+    pub fn decode_block_into(&mut self, out: &mut [i32]) -> Result<usize, Error> {
+        self.buffered = 0..0;
+        if self.current_block >= self.block_offsets.len() {
+            return Ok(0);
+        }
+
+        let index = self.current_block;
+        let channels = self.header.channels as usize;
+        let frames = self.header.block_frames(index);
+        if out.len() < frames * channels {
+            return Err(Error::InvalidSampleCount(out.len()));
+        }
+
+        self.current_block += 1;
+        let start = self.read_block_payload(index)?;
+        let out = &mut out[..frames * channels];
+        decode_block_with(
+            &self.payload,
+            &self.header,
+            self.strict_flags,
+            |channel, samples, _| {
+                interleave_channel(samples.iter().copied(), channel, channels, out)
+            },
+        )
+        .map_err(|e| e.with_block(index, start))?;
+
+        Ok(frames)
+    }
+
+    /// Fill `out` with as many whole interleaved frames as are left, continuing from where the
+    /// previous call stopped unless `current_block` has been changed since. Returns the number of
+    /// frames written, which is 0 once every frame has been read.
+    ///
+    /// Nothing is allocated after `reserve_buffers`.
+    pub fn read_frames_into(&mut self, out: &mut [i32]) -> Result<usize, Error> {
+        let channels = self.header.channels as usize;
+        let capacity = out.len() / channels;
+
+        if self.current_block != self.buffered_next {
+            self.buffered = 0..0;
+        }
+
+        let mut written = 0;
+        while written < capacity {
+            if self.buffered.is_empty() {
+                if self.current_block >= self.block_offsets.len() {
+                    break;
                 }
+                self.buffer_block(self.current_block)?;
             }
+
+            let len = self.buffered.len().min(capacity - written);
+            let frames = self.buffered.start..self.buffered.start + len;
+            self.copy_scratch_frames(frames, &mut out[written * channels..]);
+
+            self.buffered.start += len;
+            written += len;
         }
 
-        // interleave all samples
-        let mut interleaved_samples = Vec::new();
-        for i in 0..self.header.num_samples as usize {
-            for c in 0..self.header.channels as usize {
-                interleaved_samples.push(channels[c][i]);
-            }
+        Ok(written)
+    }
+
+    /// Seek to `frame`, returning the interleaved samples from `frame` up to the end of the
+    /// block containing it. Subsequent calls to `next_block` continue from the following block,
+    /// while `read_frames_into` continues from `frame` itself.
+    pub fn seek_to_frame(&mut self, frame: usize) -> Result<Vec<i32>, Error> {
+        if frame > self.header.num_samples as usize {
+            return Err(Error::FrameOutOfRange(frame));
         }
 
-        Ok(interleaved_samples)
-    }
-
-    // /// Decode all blocks into contiguous 32-bit PCM samples.
-    // pub fn decode_contiguous_samples(&mut self) -> Result<Vec<i32>, Error> {
-    //     let total_samples = self.header.num_samples as usize * self.header.channels as usize;
-    //     let mut samples = Vec::new();
-    //
-    //     let overflow_samples =
-    //         (total_samples % MAX_SAMPLES_PER_BLOCK) / self.header.channels as usize;
-    //
-    //     for i in 0..self.block_offsets.len() {
-    //         let is_final_block: bool = i == self.block_offsets.len() - 1;
-    //
-    //         // Seek to current block
-    //         self.reader.seek(SeekFrom::Start(
-    //             self.header.data_offset as u64 + self.block_offsets[i] as u64,
-    //         ))?;
-    //
-    //         for _ in 0..self.header.channels {
-    //             let block_header = BlockHeader::read(&mut self.reader)?;
-    //
-    //             let bits = block_header.bits.unsigned_abs();
-    //             let data = self.reader.read_bytes(bits as usize * 64)?;
-    //
-    //             let mut current_sample = 0;
-    //             for sample in self.read_i32_block(&data.clone(), &block_header) {
-    //                 let is_final_sample: bool = current_sample >= overflow_samples;
-    //
-    //                 current_sample += 1;
-    //
-    //                 // if we are on the final block
-    //                 if is_final_block && is_final_sample {
-    //                 } else {
-    //                     samples.push(sample);
-    //                 }
-    //             }
-    //         }
-    //     }
-    //
-    //     return Ok(samples);
-    // }
-}
+        let index = frame / MAX_SAMPLES_PER_BLOCK;
+        self.buffered = 0..0;
+        if index >= self.block_offsets.len() {
+            self.current_block = self.block_offsets.len();
+            return Ok(Vec::new());
+        }
+
+        // Discard the frames preceding `frame`
+        self.buffer_block(index)?;
+        self.buffered.start = frame % MAX_SAMPLES_PER_BLOCK;
 
-fn decode_delta_block_i32(base_sample: i32, deltas: &[u8], bits: usize) -> Vec<i32> {
-    assert_eq!(deltas.len(), bits * 64);
+        let mut samples = vec![0; self.buffered.len() * self.header.channels as usize];
+        self.copy_scratch_frames(self.buffered.clone(), &mut samples);
+        Ok(samples)
+    }
 
-    let mut samples: Vec<i32> = vec![0; MAX_SAMPLES_PER_BLOCK];
-    let mut prev_base = base_sample;
-    let delta_values = read_packed_values_i32(deltas, bits);
+    /// Iterate over the remaining blocks, yielding up to 512 interleaved frames at a time.
+    pub fn blocks(&mut self) -> Blocks<'_, R> {
+        Blocks { ncw: self }
+    }
 
-    for (i, delta) in delta_values.iter().enumerate() {
-        samples[i] = prev_base;
-        prev_base += delta;
+    /// Iterate over the header of every block and channel without decoding any audio.
+    pub fn block_infos(&mut self) -> BlockInfos<'_, R> {
+        BlockInfos {
+            ncw: self,
+            block: 0,
+            channel: 0,
+            offset: 0,
+        }
     }
 
-    samples
-}
+    /// Read the block header at `offset` for `channel` of block `index`.
+    fn read_block_info(
+        &mut self,
+        index: usize,
+        channel: usize,
+        offset: u64,
+    ) -> Result<BlockInfo, Error> {
+        self.reader.seek(SeekFrom::Start(offset))?;
+        let header = BlockHeader::read(&mut self.reader).map_err(|e| e.in_block(index, offset))?;
+
+        Ok(BlockInfo {
+            block: index,
+            channel,
+            offset,
+            data_len: header.data_len(self.header.bits_per_sample),
+            channel_encoding: header.channel_encoding(),
+            sample_format: header.sample_format(),
+            header,
+        })
+    }
 
-fn decode_truncated_block_i32(data: &[u8], bit_size: usize) -> Vec<i32> {
-    let mut samples: Vec<i32> = Vec::new();
-    let mut bit_offset = 0;
+    /// Decode a single block into interleaved samples.
+    fn decode_block(&mut self, index: usize) -> Result<Vec<i32>, Error> {
+        let start = self.read_block_payload(index)?;
+        decode_block_frames(
+            &self.payload,
+            &self.header,
+            self.strict_flags,
+            self.header.block_frames(index),
+        )
+        .map_err(|e| e.with_block(index, start))
+    }
 
-    while bit_offset + bit_size <= (data.len() * 8) {
-        let byte_offset = bit_offset / 8;
-        let bit_remainder = bit_offset % 8;
+    /// Decode a single block into interleaved float samples.
+    fn decode_block_f32(&mut self, index: usize) -> Result<Vec<f32>, Error> {
+        let start = self.read_block_payload(index)?;
+        let scale = 1.0 / (1_u64 << (self.header.bits_per_sample.max(1) - 1)) as f32;
+
+        let channels = self.header.channels as usize;
+        let mut samples = vec![0.0; self.header.block_frames(index) * channels];
+        decode_block_with(
+            &self.payload,
+            &self.header,
+            self.strict_flags,
+            |channel, decoded, format| match format {
+                SampleFormat::Float => interleave_channel(
+                    decoded.iter().map(|s| f32::from_bits(*s as u32)),
+                    channel,
+                    channels,
+                    &mut samples,
+                ),
+                SampleFormat::PCM => interleave_channel(
+                    decoded.iter().map(|s| *s as f32 * scale),
+                    channel,
+                    channels,
+                    &mut samples,
+                ),
+            },
+        )
+        .map_err(|e| e.with_block(index, start))?;
+
+        Ok(samples)
+    }
 
-        let mut temp: i32 = 0;
-        for i in 0..((bit_size + 7) / 8) {
-            temp |= (data[byte_offset + i] as i32) << (i * 8);
+    /// Decode block `index` into `scratch` for `read_frames_into`, and advance `current_block` past
+    /// it.
+    fn buffer_block(&mut self, index: usize) -> Result<(), Error> {
+        if self.scratch.is_empty() {
+            self.scratch = vec![0; MAX_SAMPLES_PER_BLOCK * self.header.channels as usize];
         }
-        let value = (temp >> bit_remainder) & ((1 << bit_size) - 1);
-        samples.push(value);
 
-        bit_offset += bit_size;
+        self.current_block = index + 1;
+        let start = self.read_block_payload(index)?;
+        decode_block_channels(
+            &self.payload,
+            &self.header,
+            self.strict_flags,
+            &mut self.scratch,
+        )
+        .map_err(|e| e.with_block(index, start))?;
+        self.buffered = 0..self.header.block_frames(index);
+        self.buffered_next = self.current_block;
+        Ok(())
     }
 
-    samples
-}
-
-fn read_packed_values_i32(data: &[u8], precision_in_bits: usize) -> Vec<i32> {
-    let mut values: Vec<i32> = Vec::new();
-    let mut bit_accumulator: i32 = 0;
-    let mut bits_in_accumulator: usize = 0;
-    let mut byte_index = 0;
-
-    while byte_index < data.len() {
-        // Accumulate more bits
-        bit_accumulator |= (data[byte_index] as i32) << bits_in_accumulator;
-        bits_in_accumulator += 8;
-        byte_index += 1;
-
-        // Extract values as long as enough bits are available
-        while bits_in_accumulator >= precision_in_bits {
-            let mut value = bit_accumulator & ((1 << precision_in_bits) - 1);
-            if value & (1 << (precision_in_bits - 1)) != 0 {
-                value |= !0 << precision_in_bits;
+    /// Interleave `frames` of the planar samples in `scratch` into the start of `out`.
+    fn copy_scratch_frames(&self, frames: Range<usize>, out: &mut [i32]) {
+        let channels = self.header.channels as usize;
+        for (i, frame) in frames.enumerate() {
+            for channel in 0..channels {
+                out[i * channels + channel] = self.scratch[channel * MAX_SAMPLES_PER_BLOCK + frame];
             }
-            values.push(value);
+        }
+    }
 
-            // Remove used bits
-            bit_accumulator >>= precision_in_bits;
-            bits_in_accumulator -= precision_in_bits;
+    /// Read the block headers and data of every channel of block `index` into `payload`,
+    /// returning the absolute offset of the block.
+    fn read_block_payload(&mut self, index: usize) -> Result<u64, Error> {
+        let start = self.header.block_start(index, self.block_offsets[index])?;
+        self.reader.seek(SeekFrom::Start(start))?;
+
+        // The buffer is only ever grown for the first block
+        self.payload.clear();
+        self.payload.reserve(self.header.max_payload_len());
+        for _ in 0..self.header.channels {
+            let offset = self.payload.len();
+            self.read_channel_payload()
+                .map_err(|e| e.in_block(index, start + offset as u64))?;
         }
+
+        Ok(start)
     }
 
-    values
+    /// Append the block header and data of a single channel to `payload`.
+    fn read_channel_payload(&mut self) -> Result<(), Error> {
+        let offset = self.payload.len();
+        self.payload.resize(offset + BLOCK_HEADER_SIZE, 0);
+        self.reader.read_bytes_into(&mut self.payload[offset..])?;
+        let data_len = BlockHeader::parse(&self.payload[offset..])?
+            .checked_data_len(self.header.bits_per_sample)?;
+
+        let data = self.payload.len();
+        self.payload.resize(data + data_len, 0);
+        self.reader.read_bytes_into(&mut self.payload[data..])
+    }
 }
 
-#[derive(Debug, PartialEq)]
-pub enum ChannelEncoding {
-    LeftRight,
-    MidSide,
+/// Streaming iterator over the decoded blocks of an [`NcwReader`].
+pub struct Blocks<'a, R> {
+    ncw: &'a mut NcwReader<R>,
 }
 
-#[derive(Debug, PartialEq)]
-pub enum SampleFormat {
-    PCM,
-    Float,
+/// Iterator over the block headers of an [`NcwReader`], yielding one [`BlockInfo`] per channel
+/// of each block. After an error the remaining channels of that block are skipped.
+pub struct BlockInfos<'a, R> {
+    ncw: &'a mut NcwReader<R>,
+    block: usize,
+    channel: usize,
+    offset: u64,
 }
 
-impl BlockHeader {
-    pub fn read<R: ReadBytesExt>(mut reader: R) -> Result<BlockHeader, Error> {
-        let mut block_reader = Cursor::new(reader.read_bytes(BLOCK_HEADER_SIZE)?);
+impl<R: Read + Seek> Iterator for BlockInfos<'_, R> {
+    type Item = Result<BlockInfo, Error>;
 
-        let magic = block_reader.read_u32_be()?;
-        assert_eq!(magic, 0x160C9A3E);
+    fn next(&mut self) -> Option<Self::Item> {
+        let block_offset = *self.ncw.block_offsets.get(self.block)?;
 
-        Ok(BlockHeader {
-            base_value: block_reader.read_i32_le()?,
-            bits: block_reader.read_i16_le()?,
-            flags: block_reader.read_u16_le()?,
-        })
-    }
+        if self.channel == 0 {
+            match self.ncw.header.block_start(self.block, block_offset) {
+                Ok(start) => self.offset = start,
+                Err(e) => {
+                    self.block += 1;
+                    return Some(Err(e));
+                }
+            }
+        }
 
-    pub fn channel_encoding(&self) -> ChannelEncoding {
-        if self.flags & 0b0000000000000001 == 0b0000000000000001 {
-            ChannelEncoding::MidSide
-        } else {
-            ChannelEncoding::LeftRight
+        let info = self
+            .ncw
+            .read_block_info(self.block, self.channel, self.offset);
+
+        match &info {
+            Ok(info) => {
+                self.offset += (BLOCK_HEADER_SIZE + info.data_len) as u64;
+                self.channel += 1;
+                if self.channel == self.ncw.header.channels as usize {
+                    self.channel = 0;
+                    self.block += 1;
+                }
+            }
+            Err(_) => {
+                self.channel = 0;
+                self.block += 1;
+            }
         }
+
+        Some(info)
     }
-    pub fn sample_format(&self) -> SampleFormat {
-        if self.flags & 0b0000000000000010 == 0b0000000000000010 {
-            SampleFormat::Float
-        } else {
-            SampleFormat::PCM
-        }
+}
+
+impl<R: Read + Seek> Iterator for Blocks<'_, R> {
+    type Item = Result<Vec<i32>, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.ncw.next_block().transpose()
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let remaining = self
+            .ncw
+            .block_offsets
+            .len()
+            .saturating_sub(self.ncw.current_block);
+        (remaining, Some(remaining))
+    }
+}
+
+impl BlockHeader {
+    /// Read a block header. An invalid magic is reported with its byte offset; the block index
+    /// is only known (and filled in) when reading through [`NcwReader`].
+    pub fn read<R: ReadBytesExt>(mut reader: R) -> Result<BlockHeader, Error> {
+        let offset = reader.stream_position()?;
+        let mut bytes = [0; BLOCK_HEADER_SIZE];
+        reader.read_bytes_into(&mut bytes)?;
+
+        Self::parse(&bytes).map_err(|e| e.in_block(0, offset))
+    }
+
+    /// Write the 16-byte block header.
+    pub fn write<W: Write>(&self, mut writer: W) -> Result<(), Error> {
+        writer.write_all(&BLOCK_MAGIC.to_be_bytes())?;
+        writer.write_all(&self.base_value.to_le_bytes())?;
+        writer.write_all(&self.bits.to_le_bytes())?;
+        writer.write_all(&self.flags.bits().to_le_bytes())?;
+        writer.write_all(&[0; 4])?;
+        Ok(())
     }
 }
 
 impl NcwHeader {
     pub fn read<R: ReadBytesExt>(mut reader: R) -> Result<Self, Error> {
-        let mut reader = Cursor::new(reader.read_bytes(HEADER_SIZE)?);
+        let mut bytes = [0; HEADER_SIZE];
+        reader.read_bytes_into(&mut bytes)?;
 
-        let magic = reader.read_u64_be()?;
-        assert!([0x01A89ED631010000, 0x01A89ED630010000].contains(&magic));
+        Self::parse(&bytes)
+    }
 
-        Ok(Self {
-            channels: reader.read_u16_le()?,
-            bits_per_sample: reader.read_u16_le()?,
-            sample_rate: reader.read_u32_le()?,
-            num_samples: reader.read_u32_le()?,
-            blocks_offset: reader.read_u32_le()?,
-            data_offset: reader.read_u32_le()?,
-            data_size: reader.read_u32_le()?,
-        })
+    /// Write the full 120-byte header, including any reserved bytes read from the original file.
+    pub fn write<W: Write>(&self, mut writer: W) -> Result<(), Error> {
+        writer.write_all(&self.version.magic().to_be_bytes())?;
+        writer.write_all(&self.channels.to_le_bytes())?;
+        writer.write_all(&self.bits_per_sample.to_le_bytes())?;
+        writer.write_all(&self.sample_rate.to_le_bytes())?;
+        writer.write_all(&self.num_samples.to_le_bytes())?;
+        writer.write_all(&self.blocks_offset.to_le_bytes())?;
+        writer.write_all(&self.data_offset.to_le_bytes())?;
+        writer.write_all(&self.data_size.to_le_bytes())?;
+        writer.write_all(&self.format.to_le_bytes())?;
+        writer.write_all(&self.reserved)?;
+        Ok(())
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::test_util::{
+        read_wav, read_wav_data, stereo_with_corrupt_num_samples, unknown_flag_with_extra_flag,
+        STEREO_SECOND_BLOCK, UNKNOWN_FLAG_RIGHT_CHANNEL,
+    };
+    use crate::{BlockFlags, NcwVersion};
     use std::fs::File;
+    use std::io::Cursor;
 
     #[test]
     fn test_read_16bit_mono() -> Result<(), Error> {
@@ -321,7 +609,7 @@ mod tests {
 
         assert_eq!(samples[0], 0x0000);
         assert_eq!(samples[16], 0x001B);
-        assert_eq!(samples[32], 0xFF5A);
+        assert_eq!(samples[32], 0xFF5A_u16 as i16 as i32);
 
         assert_eq!(
             ncw.header.num_samples as usize,
@@ -362,6 +650,696 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_stream_blocks() -> Result<(), Error> {
+        let file = File::open("tests/data/16-bit-stereo.ncw")?;
+        let mut ncw = NcwReader::read(file)?;
+        let samples = ncw.decode_samples()?;
+        let channels = ncw.header.channels as usize;
+
+        let mut streamed = Vec::new();
+        for block in ncw.blocks() {
+            let block = block?;
+            assert!(block.len() <= MAX_SAMPLES_PER_BLOCK * channels);
+            streamed.extend(block);
+        }
+
+        assert_eq!(streamed, samples);
+        assert_eq!(ncw.current_block, ncw.block_offsets.len());
+        assert!(ncw.next_block()?.is_none());
+        Ok(())
+    }
+
+    /// Counts allocations made by the current thread, so tests can assert a path never
+    /// allocates.
+    struct CountingAllocator;
+
+    thread_local! {
+        static ALLOCATIONS: std::cell::Cell<usize> = const { std::cell::Cell::new(0) };
+    }
+
+    unsafe impl std::alloc::GlobalAlloc for CountingAllocator {
+        unsafe fn alloc(&self, layout: std::alloc::Layout) -> *mut u8 {
+            ALLOCATIONS.with(|count| count.set(count.get() + 1));
+            std::alloc::System.alloc(layout)
+        }
+
+        unsafe fn dealloc(&self, ptr: *mut u8, layout: std::alloc::Layout) {
+            std::alloc::System.dealloc(ptr, layout)
+        }
+    }
+
+    #[global_allocator]
+    static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+    fn allocations() -> usize {
+        ALLOCATIONS.with(|count| count.get())
+    }
+
+    #[test]
+    fn test_decode_block_into() -> Result<(), Error> {
+        for name in [
+            "16-bit-mono",
+            "24-bit-stereo",
+            "16-bit-stereo-midside",
+            "unknown-flag",
+        ] {
+            let data = std::fs::read(format!("tests/data/{name}.ncw"))?;
+            let expected: Vec<Vec<i32>> = NcwReader::read(Cursor::new(&data))?
+                .blocks()
+                .collect::<Result<_, _>>()?;
+
+            let mut ncw = NcwReader::read(Cursor::new(&data))?;
+            let channels = ncw.header.channels as usize;
+            let mut out = vec![0; MAX_SAMPLES_PER_BLOCK * channels];
+
+            ncw.reserve_buffers();
+
+            let mut blocks = Vec::with_capacity(expected.len());
+            let before = allocations();
+            loop {
+                let frames = ncw.decode_block_into(&mut out)?;
+                if frames == 0 {
+                    break;
+                }
+                blocks.push(frames);
+            }
+            assert_eq!(allocations(), before, "{name} allocated");
+
+            assert_eq!(blocks.len(), expected.len());
+            ncw.current_block = 0;
+            for (frames, expected) in blocks.into_iter().zip(&expected) {
+                assert_eq!(ncw.decode_block_into(&mut out)?, frames);
+                assert_eq!(out[..frames * channels], expected[..]);
+            }
+        }
+
+        // The final partial block fits a shorter buffer, full blocks do not
+        let mut ncw = NcwReader::read(File::open("tests/data/16-bit-stereo.ncw")?)?;
+        assert!(matches!(
+            ncw.decode_block_into(&mut [0; 100]),
+            Err(Error::InvalidSampleCount(100))
+        ));
+        ncw.current_block = ncw.block_offsets.len() - 1;
+        let frames = ncw.header.num_samples as usize % MAX_SAMPLES_PER_BLOCK;
+        assert_eq!(ncw.decode_block_into(&mut vec![0; frames * 2])?, frames);
+        Ok(())
+    }
+
+    #[test]
+    fn test_read_frames_into() -> Result<(), Error> {
+        let data = std::fs::read("tests/data/16-bit-stereo-midside.ncw")?;
+        let expected = NcwReader::read(Cursor::new(&data))?.decode_samples()?;
+
+        for frames in [1, 100, 511, 512, 513, 5000] {
+            let mut ncw = NcwReader::read(Cursor::new(&data))?;
+            // A trailing partial frame is left untouched
+            let mut out = vec![0; frames * 2 + 1];
+            let mut samples = Vec::with_capacity(expected.len());
+
+            ncw.reserve_buffers();
+            loop {
+                let before = allocations();
+                let read = ncw.read_frames_into(&mut out)?;
+                assert_eq!(allocations(), before);
+                if read == 0 {
+                    break;
+                }
+                samples.extend_from_slice(&out[..read * 2]);
+            }
+            assert_eq!(samples, expected, "reading {frames} frames at a time");
+        }
+
+        // Corrupt blocks are reported as by decode_samples
+        let mut corrupt = std::fs::read("tests/data/16-bit-stereo.ncw")?;
+        corrupt[STEREO_SECOND_BLOCK] = 0;
+        let mut ncw = NcwReader::read(Cursor::new(corrupt))?;
+        let mut out = vec![0; MAX_SAMPLES_PER_BLOCK * 2];
+        assert_eq!(ncw.read_frames_into(&mut out)?, 512);
+        assert!(matches!(
+            ncw.read_frames_into(&mut out),
+            Err(Error::InvalidBlockMagic { block: 1, offset })
+                if offset == STEREO_SECOND_BLOCK as u64
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_reader_buffers() -> Result<(), Error> {
+        // Buffers are not sized by the channel count until they are reserved or used
+        let data = std::fs::read("tests/data/16-bit-stereo-midside.ncw")?;
+        let mut wide = data.clone();
+        wide[8..10].copy_from_slice(&u16::MAX.to_le_bytes());
+        let ncw = NcwReader::read(Cursor::new(&wide))?;
+        assert_eq!(ncw.payload.capacity() + ncw.scratch.capacity(), 0);
+
+        let mut ncw = NcwReader::read(Cursor::new(&data))?;
+        ncw.reserve_buffers();
+        assert_eq!(ncw.payload.capacity(), (BLOCK_HEADER_SIZE + 512 * 4) * 2);
+        assert_eq!(ncw.scratch.len(), 512 * 2);
+
+        let expected = NcwReader::read(Cursor::new(&data))?.decode_samples()?;
+        let mut ncw = NcwReader::read(Cursor::new(&data))?;
+        let mut out = vec![0; 100 * 2];
+        assert_eq!(ncw.read_frames_into(&mut out)?, 100);
+
+        // Changing current_block discards the rest of the buffered block
+        ncw.current_block = 0;
+        assert_eq!(ncw.read_frames_into(&mut out)?, 100);
+        assert_eq!(out, expected[..200]);
+        ncw.current_block = 2;
+        assert_eq!(ncw.read_frames_into(&mut out)?, 100);
+        assert_eq!(out, expected[2048..2248]);
+
+        // As does decoding a whole block
+        let mut block = vec![0; MAX_SAMPLES_PER_BLOCK * 2];
+        assert_eq!(ncw.decode_block_into(&mut block)?, 512);
+        assert_eq!(block, expected[3072..4096]);
+        assert_eq!(ncw.read_frames_into(&mut out)?, 100);
+        assert_eq!(out, expected[4096..4296]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_seek_to_frame() -> Result<(), Error> {
+        let file = File::open("tests/data/16-bit-stereo.ncw")?;
+        let mut ncw = NcwReader::read(file)?;
+        let samples = ncw.decode_samples()?;
+
+        let frame = 1000;
+        let mut seeked = ncw.seek_to_frame(frame)?;
+        assert_eq!(seeked.len(), (1024 - frame) * 2);
+
+        while let Some(block) = ncw.next_block()? {
+            seeked.extend(block);
+        }
+        assert_eq!(seeked, samples[frame * 2..]);
+
+        // read_frames_into resumes at the frame sought to, not the next block
+        for frame in [0, 1000, 1024, 1100] {
+            ncw.seek_to_frame(frame)?;
+            let mut out = vec![0; 200 * 2];
+            assert_eq!(ncw.read_frames_into(&mut out)?, 200);
+            assert_eq!(out, samples[frame * 2..(frame + 200) * 2]);
+        }
+
+        assert!(ncw
+            .seek_to_frame(ncw.header.num_samples as usize)?
+            .is_empty());
+        assert!(ncw
+            .seek_to_frame(ncw.header.num_samples as usize + 1)
+            .is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_decode_range() -> Result<(), Error> {
+        let file = File::open("tests/data/testfile-onezero-16-bit-stereo-multiblock.ncw")?;
+        let mut ncw = NcwReader::read(file)?;
+        let samples = ncw.decode_samples()?;
+        let num_frames = ncw.header.num_samples as usize;
+
+        for (start, len) in [
+            (0, 10),
+            (100, 412),
+            (500, 30),
+            (512, 38),
+            (3, num_frames - 3),
+        ] {
+            assert_eq!(
+                ncw.decode_range(start, len)?,
+                samples[start * 2..(start + len) * 2],
+                "range {start}..{}",
+                start + len
+            );
+        }
+
+        assert!(ncw.decode_range(num_frames, 0)?.is_empty());
+        assert!(ncw.decode_range(num_frames - 1, 2).is_err());
+
+        // Shrink the block table to a single block, leaving num_samples at 2148 frames
+        let mut data = std::fs::read("tests/data/16-bit-stereo-midside.ncw")?;
+        data[20..24].copy_from_slice(&136_u32.to_le_bytes());
+        let mut ncw = NcwReader::read(Cursor::new(data))?;
+        assert_eq!(ncw.block_offsets.len(), 1);
+        assert_eq!(ncw.decode_range(0, 512)?.len(), 1024);
+        assert!(matches!(
+            ncw.decode_range(0, 2148),
+            Err(Error::FrameOutOfRange(2148))
+        ));
+        Ok(())
+    }
+
+    /// Build a mono NCW file holding a single uncompressed block.
+    fn raw_block_ncw(bits_per_sample: u16, flags: u16, samples: &[i32]) -> Vec<u8> {
+        let bytes_per_sample = bits_per_sample as usize / 8;
+
+        let mut block = Vec::new();
+        block.extend(BLOCK_MAGIC.to_be_bytes());
+        block.extend(0_i32.to_le_bytes());
+        block.extend(0_i16.to_le_bytes());
+        block.extend(flags.to_le_bytes());
+        block.extend([0; 4]);
+        for sample in samples {
+            block.extend(&sample.to_le_bytes()[..bytes_per_sample]);
+        }
+
+        let mut ncw = Vec::new();
+        ncw.extend(NcwVersion::default().magic().to_be_bytes());
+        ncw.extend(1_u16.to_le_bytes());
+        ncw.extend(bits_per_sample.to_le_bytes());
+        ncw.extend(44100_u32.to_le_bytes());
+        ncw.extend((samples.len() as u32).to_le_bytes());
+        ncw.extend((HEADER_SIZE as u32).to_le_bytes());
+        ncw.extend((HEADER_SIZE as u32 + 8).to_le_bytes());
+        ncw.extend((block.len() as u32).to_le_bytes());
+        ncw.resize(HEADER_SIZE, 0);
+        ncw.extend(0_u32.to_le_bytes());
+        ncw.extend((block.len() as u32).to_le_bytes());
+        ncw.extend(block);
+        ncw
+    }
+
+    #[test]
+    fn test_read_raw_blocks() -> Result<(), Error> {
+        for bits_per_sample in [8, 16, 24, 32] {
+            let min = -(1_i64 << (bits_per_sample - 1));
+            let max = (1_i64 << (bits_per_sample - 1)) - 1;
+            let samples: Vec<i32> = (0..MAX_SAMPLES_PER_BLOCK as i64)
+                .map(|i| match i % 4 {
+                    0 => min,
+                    1 => max,
+                    2 => (-i).max(min),
+                    _ => i % 100,
+                } as i32)
+                .collect();
+
+            let data = raw_block_ncw(bits_per_sample, 0, &samples);
+            let mut ncw = NcwReader::read(Cursor::new(data))?;
+            assert_eq!(ncw.decode_samples()?, samples, "{bits_per_sample}-bit");
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_read_raw_float_block() -> Result<(), Error> {
+        let samples: Vec<f32> = (0..MAX_SAMPLES_PER_BLOCK)
+            .map(|i| (i as f32 * 0.1).sin() * 0.75)
+            .collect();
+        let bits: Vec<i32> = samples.iter().map(|s| s.to_bits() as i32).collect();
+
+        let data = raw_block_ncw(32, 0b10, &bits);
+        let mut ncw = NcwReader::read(Cursor::new(data))?;
+        assert_eq!(ncw.decode_samples_f32()?, samples);
+        Ok(())
+    }
+
+    fn read_corrupted(corrupt: impl FnOnce(&mut Vec<u8>)) -> Result<Vec<i32>, Error> {
+        let mut data = std::fs::read("tests/data/16-bit-stereo.ncw")?;
+        corrupt(&mut data);
+        NcwReader::read(Cursor::new(data))?.decode_samples()
+    }
+
+    #[test]
+    fn test_read_version() -> Result<(), Error> {
+        let mut data = std::fs::read("tests/data/16-bit-stereo.ncw")?;
+        let mut ncw = NcwReader::read(Cursor::new(&data))?;
+        assert_eq!(ncw.header.version, NcwVersion::V2);
+        let samples = ncw.decode_samples()?;
+
+        data[..8].copy_from_slice(&NcwVersion::V1.magic().to_be_bytes());
+        let mut ncw = NcwReader::read(Cursor::new(&data))?;
+        assert_eq!(ncw.header.version, NcwVersion::V1);
+        assert_eq!(ncw.decode_samples()?, samples);
+        Ok(())
+    }
+
+    #[test]
+    fn test_header_roundtrip() -> Result<(), Error> {
+        for name in [
+            "16-bit-mono",
+            "24-bit-stereo",
+            "32-bit-mono-float",
+            "unknown-flag",
+        ] {
+            let data = std::fs::read(format!("tests/data/{name}.ncw"))?;
+            let header = NcwHeader::read(Cursor::new(&data))?;
+
+            let mut written = Vec::new();
+            header.write(&mut written)?;
+            assert_eq!(written, data[..HEADER_SIZE], "{name}");
+        }
+
+        let header = NcwHeader::read(File::open("tests/data/unknown-flag.ncw")?)?;
+        assert_eq!(header.sample_format(), SampleFormat::Float);
+        assert_eq!(&header.reserved[..4], &[b'1', 0, b'_', 0]);
+
+        let header = NcwHeader::read(File::open("tests/data/24-bit-stereo.ncw")?)?;
+        assert_eq!(header.sample_format(), SampleFormat::PCM);
+        assert_eq!(header.reserved, [0; 84]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_block_infos() -> Result<(), Error> {
+        let file = File::open("tests/data/16-bit-stereo.ncw")?;
+        let mut ncw = NcwReader::read(file)?;
+        let data_offset = ncw.header.data_offset as u64;
+        let block_offsets = ncw.block_offsets.clone();
+
+        let infos = ncw.block_infos().collect::<Result<Vec<_>, _>>()?;
+        assert_eq!(infos.len(), block_offsets.len() * 2);
+
+        for pair in infos.chunks_exact(2) {
+            let (left, right) = (&pair[0], &pair[1]);
+            assert_eq!((left.channel, right.channel), (0, 1));
+            assert_eq!(left.offset, data_offset + block_offsets[left.block] as u64);
+            assert_eq!(
+                right.offset,
+                left.offset + (BLOCK_HEADER_SIZE + left.data_len) as u64
+            );
+            assert_eq!(left.data_len, left.header.bits.unsigned_abs() as usize * 64);
+            assert_eq!(left.channel_encoding, ChannelEncoding::LeftRight);
+            assert_eq!(left.sample_format, SampleFormat::PCM);
+
+            if let Some(next) = block_offsets.get(left.block + 1) {
+                assert_eq!(
+                    right.offset + (BLOCK_HEADER_SIZE + right.data_len) as u64,
+                    data_offset + *next as u64
+                );
+            }
+        }
+
+        let file = File::open("tests/data/32-bit-mono-float.ncw")?;
+        let mut ncw = NcwReader::read(file)?;
+        for info in ncw.block_infos() {
+            assert_eq!(info?.sample_format, SampleFormat::Float);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_block_flags() {
+        let flags = BlockFlags::from_bits(0b1010);
+        assert!(flags.contains(BlockFlags::FLOAT));
+        assert!(!flags.contains(BlockFlags::MID_SIDE));
+        assert!(!flags.contains(BlockFlags::MID_SIDE | BlockFlags::FLOAT));
+        assert_eq!(flags.unknown_bits(), 0b1000);
+        assert_eq!((BlockFlags::MID_SIDE | BlockFlags::FLOAT).unknown_bits(), 0);
+    }
+
+    #[test]
+    fn test_strict_flags() -> Result<(), Error> {
+        let data = std::fs::read("tests/data/unknown-flag.ncw")?;
+        let samples = NcwReader::read(Cursor::new(&data))?.decode_samples()?;
+
+        let mut ncw = NcwReader::read(Cursor::new(unknown_flag_with_extra_flag()?))?;
+        assert_eq!(ncw.decode_samples()?, samples);
+
+        ncw.strict_flags = true;
+        assert!(matches!(
+            ncw.decode_samples(),
+            Err(Error::UnknownBlockFlags { block: 0, offset, flags: 0b110 })
+                if offset == UNKNOWN_FLAG_RIGHT_CHANNEL as u64
+        ));
+
+        let info = ncw.block_infos().nth(1).unwrap()?;
+        assert_eq!(info.header.flags.unknown_bits(), 0b100);
+        Ok(())
+    }
+
+    #[cfg(feature = "rayon")]
+    #[test]
+    fn test_decode_samples_parallel() -> Result<(), Error> {
+        for entry in std::fs::read_dir("tests/data")? {
+            let path = entry?.path();
+            if path.extension().is_none_or(|ext| ext != "ncw") {
+                continue;
+            }
+
+            let mut ncw = NcwReader::read(File::open(&path)?)?;
+            assert_eq!(ncw.decode_samples_parallel()?, ncw.decode_samples()?);
+        }
+
+        let mut data = std::fs::read("tests/data/16-bit-stereo.ncw")?;
+        data[STEREO_SECOND_BLOCK] = 0;
+        let mut ncw = NcwReader::read(Cursor::new(data))?;
+        assert!(matches!(
+            ncw.decode_samples_parallel(),
+            Err(Error::InvalidBlockMagic { block: 1, offset })
+                if offset == STEREO_SECOND_BLOCK as u64
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_corrupt_files() {
+        let second_block = STEREO_SECOND_BLOCK;
+
+        assert!(matches!(
+            read_corrupted(|data| data[0] = 0),
+            Err(Error::InvalidFileSignature)
+        ));
+        assert!(matches!(
+            read_corrupted(|data| data[8..10].copy_from_slice(&0_u16.to_le_bytes())),
+            Err(Error::InvalidChannelCount(0))
+        ));
+        assert!(matches!(
+            read_corrupted(|data| data[10..12].copy_from_slice(&12_u16.to_le_bytes())),
+            Err(Error::UnsupportedBitDepth(12))
+        ));
+        assert!(matches!(
+            read_corrupted(|data| data[second_block] = 0),
+            Err(Error::InvalidBlockMagic { block: 1, offset }) if offset == second_block as u64
+        ));
+        assert!(matches!(
+            read_corrupted(|data| data[second_block + 8..second_block + 10]
+                .copy_from_slice(&40_i16.to_le_bytes())),
+            Err(Error::UnsupportedBitDepth(40))
+        ));
+        assert!(matches!(
+            read_corrupted(|data| data[124..128].copy_from_slice(&u32::MAX.to_le_bytes())),
+            Err(Error::BlockOffsetOutOfRange {
+                block: 1,
+                offset: u32::MAX
+            })
+        ));
+        assert!(matches!(
+            read_corrupted(|data| data.truncate(second_block + 20)),
+            Err(Error::TruncatedBlock { block: 1, offset }) if offset == second_block as u64
+        ));
+
+        let error = read_corrupted(|data| data[second_block] = 0).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            format!("Ncw Error: invalid magic for block 1 at offset {second_block}")
+        );
+    }
+
+    #[test]
+    fn test_corrupt_num_samples() -> Result<(), Error> {
+        let data = std::fs::read("tests/data/16-bit-stereo.ncw")?;
+        let samples = NcwReader::read(Cursor::new(&data))?.decode_samples()?;
+
+        let (corrupt, total) = stereo_with_corrupt_num_samples()?;
+        let mut ncw = NcwReader::read(Cursor::new(&corrupt))?;
+
+        let decoded = ncw.decode_samples()?;
+        assert_eq!(decoded.len(), total);
+        assert_eq!(decoded[..samples.len()], samples[..]);
+        let (lenient, diagnostics) = ncw.decode_samples_lenient()?;
+        assert_eq!(lenient, decoded);
+        assert!(diagnostics.is_empty());
+        assert_eq!(ncw.decode_samples_f32()?.len(), total);
+        Ok(())
+    }
+
+    #[test]
+    fn test_decode_samples_lenient() -> Result<(), Error> {
+        let mut data = std::fs::read("tests/data/16-bit-stereo.ncw")?;
+        let samples = NcwReader::read(Cursor::new(&data))?.decode_samples()?;
+
+        let second_block = STEREO_SECOND_BLOCK;
+        data[second_block] = 0;
+        data[124 + 4 * 2..124 + 4 * 3].copy_from_slice(&u32::MAX.to_le_bytes());
+
+        let mut ncw = NcwReader::read(Cursor::new(data))?;
+        let (lenient, diagnostics) = ncw.decode_samples_lenient()?;
+
+        assert_eq!(lenient.len(), samples.len());
+        assert_eq!(diagnostics.len(), 2);
+
+        assert_eq!(diagnostics[0].block, 1);
+        assert_eq!(diagnostics[0].offset, second_block as u64);
+        assert!(matches!(
+            diagnostics[0].error,
+            Error::InvalidBlockMagic { block: 1, .. }
+        ));
+        assert_eq!(diagnostics[1].block, 3);
+        assert!(matches!(
+            diagnostics[1].error,
+            Error::BlockOffsetOutOfRange { block: 3, .. }
+        ));
+
+        let block = 512 * 2;
+        assert_eq!(lenient[..block], samples[..block]);
+        assert!(lenient[block..block * 2].iter().all(|s| *s == 0));
+        assert_eq!(lenient[block * 2..block * 3], samples[block * 2..block * 3]);
+        assert!(lenient[block * 3..block * 4].iter().all(|s| *s == 0));
+        assert_eq!(lenient[block * 4..], samples[block * 4..]);
+        Ok(())
+    }
+
+    fn assert_matches_wav(name: &str) -> Result<(), Error> {
+        let file = File::open(format!("tests/data/{name}.ncw"))?;
+        let mut ncw = NcwReader::read(file)?;
+        let samples = ncw.decode_samples()?;
+        let wav = read_wav(&format!("tests/data/{name}.wav"));
+
+        assert_eq!(samples, wav[..samples.len()], "{name} does not match wav");
+        Ok(())
+    }
+
+    #[test]
+    fn test_matches_reference_wavs() -> Result<(), Error> {
+        for name in [
+            "16-bit-mono",
+            "16-bit-stereo",
+            "24-bit-mono",
+            "testfile-onezero-16-bit-stereo-multiblock",
+        ] {
+            assert_matches_wav(name)?;
+        }
+        Ok(())
+    }
+
+    /// The mid/side fixture is synthesized rather than encoded by Kontakt: its mid/side blocks
+    /// hold `(left + right) / 2` and `(left - right) / 2` of the WAV, the inverse of the formula
+    /// the decoder applies. This checks that reconstruction is applied per channel pair, in
+    /// alternating mid/side and left/right blocks, but cannot show that Kontakt uses the same
+    /// convention. `test_midside_fixture_generator` rebuilds both files.
+    #[test]
+    fn test_read_16bit_stereo_midside() -> Result<(), Error> {
+        let file = File::open("tests/data/16-bit-stereo-midside.ncw")?;
+        let mut ncw = NcwReader::read(file)?;
+
+        // even blocks are mid/side encoded, odd blocks left/right
+        ncw.reader
+            .seek(SeekFrom::Start(ncw.header.data_offset as u64))?;
+        let block_header = BlockHeader::read(&mut ncw.reader)?;
+        assert_eq!(block_header.channel_encoding(), ChannelEncoding::MidSide);
+
+        assert_matches_wav("16-bit-stereo-midside")
+    }
+
+    /// Pack `samples`, padded to a full block with the last sample, as one delta encoded channel
+    /// block with the smallest width that holds every delta.
+    fn push_delta_block(data: &mut Vec<u8>, samples: &[i32], flags: BlockFlags) {
+        let mut padded = samples.to_vec();
+        padded.resize(MAX_SAMPLES_PER_BLOCK, samples[samples.len() - 1]);
+        let mut deltas: Vec<i32> = padded.windows(2).map(|w| w[1] - w[0]).collect();
+        deltas.push(0);
+
+        let bits = (1..=32)
+            .find(|bits| {
+                let range = -(1i64 << (bits - 1))..1i64 << (bits - 1);
+                deltas.iter().all(|&d| range.contains(&(d as i64)))
+            })
+            .unwrap();
+
+        data.extend_from_slice(&BLOCK_MAGIC.to_be_bytes());
+        data.extend_from_slice(&padded[0].to_le_bytes());
+        data.extend_from_slice(&(bits as i16).to_le_bytes());
+        data.extend_from_slice(&flags.bits().to_le_bytes());
+        data.extend_from_slice(&[0; 4]);
+
+        let mut packed = vec![0u8; bits * 64];
+        for (i, delta) in deltas.iter().enumerate() {
+            for bit in 0..bits {
+                if delta >> bit & 1 == 1 {
+                    let pos = i * bits + bit;
+                    packed[pos / 8] |= 1 << (pos % 8);
+                }
+            }
+        }
+        data.extend_from_slice(&packed);
+    }
+
+    /// Rebuild the synthesized mid/side fixture from the first 2148 frames of 16-bit-stereo.wav
+    /// and check it matches the committed files. Even blocks store `(left + right) >> 1` and
+    /// `(left - right) >> 1` as mid/side; the wav holds what the decoder reconstructs from them,
+    /// which differs from the source by one where `left + right` is odd. Odd blocks store
+    /// left/right unchanged.
+    #[test]
+    fn test_midside_fixture_generator() -> Result<(), Error> {
+        const FRAMES: usize = 2148;
+        let source = read_wav("tests/data/16-bit-stereo.wav");
+        let (mut left, mut right): (Vec<i32>, Vec<i32>) = source[..FRAMES * 2]
+            .chunks_exact(2)
+            .map(|frame| (frame[0], frame[1]))
+            .unzip();
+
+        let mut data = Vec::new();
+        let mut block_offsets = Vec::new();
+        let blocks = left.chunks_mut(512).zip(right.chunks_mut(512));
+        for (index, (left, right)) in blocks.enumerate() {
+            block_offsets.push(data.len() as u32);
+            if index % 2 == 0 {
+                let (mid, side): (Vec<i32>, Vec<i32>) = left
+                    .iter()
+                    .zip(right.iter())
+                    .map(|(l, r)| ((l + r) >> 1, (l - r) >> 1))
+                    .unzip();
+                for (i, (m, s)) in mid.iter().zip(&side).enumerate() {
+                    left[i] = m + s;
+                    right[i] = m - s;
+                }
+                push_delta_block(&mut data, &mid, BlockFlags::MID_SIDE);
+                push_delta_block(&mut data, &side, BlockFlags::MID_SIDE);
+            } else {
+                push_delta_block(&mut data, left, BlockFlags::from_bits(0));
+                push_delta_block(&mut data, right, BlockFlags::from_bits(0));
+            }
+        }
+        block_offsets.push(data.len() as u32);
+
+        let blocks_offset = HEADER_SIZE as u32;
+        let data_offset = blocks_offset + block_offsets.len() as u32 * 4;
+        let mut ncw = vec![0x01, 0xA8, 0x9E, 0xD6, 0x31, 0x01, 0x00, 0x00];
+        ncw.extend_from_slice(&2u16.to_le_bytes());
+        ncw.extend_from_slice(&16u16.to_le_bytes());
+        for field in [48000, FRAMES as u32, blocks_offset, data_offset] {
+            ncw.extend_from_slice(&field.to_le_bytes());
+        }
+        ncw.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        ncw.resize(HEADER_SIZE, 0);
+        for offset in block_offsets {
+            ncw.extend_from_slice(&offset.to_le_bytes());
+        }
+        ncw.extend_from_slice(&data);
+        assert!(ncw == std::fs::read("tests/data/16-bit-stereo-midside.ncw")?);
+
+        let pcm: Vec<u8> = left
+            .iter()
+            .zip(&right)
+            .flat_map(|(&l, &r)| [l as i16, r as i16])
+            .flat_map(i16::to_le_bytes)
+            .collect();
+        let mut wav = b"RIFF".to_vec();
+        wav.extend_from_slice(&(36 + pcm.len() as u32).to_le_bytes());
+        wav.extend_from_slice(b"WAVEfmt ");
+        wav.extend_from_slice(&16u32.to_le_bytes());
+        wav.extend_from_slice(&[1, 0, 2, 0]);
+        wav.extend_from_slice(&48000u32.to_le_bytes());
+        wav.extend_from_slice(&(48000u32 * 4).to_le_bytes());
+        wav.extend_from_slice(&[4, 0, 16, 0]);
+        wav.extend_from_slice(b"data");
+        wav.extend_from_slice(&(pcm.len() as u32).to_le_bytes());
+        wav.extend_from_slice(&pcm);
+        assert!(wav == std::fs::read("tests/data/16-bit-stereo-midside.wav")?);
+        Ok(())
+    }
+
     #[test]
     fn test_read_24bit_mono() -> Result<(), Error> {
         let file = File::open("tests/data/24-bit-mono.ncw")?;
@@ -385,4 +1363,41 @@ mod tests {
         ncw.decode_samples()?;
         Ok(())
     }
+
+    #[test]
+    fn test_decode_samples_f32() -> Result<(), Error> {
+        for name in ["32-bit-mono-float", "unknown-flag"] {
+            let file = File::open(format!("tests/data/{name}.ncw"))?;
+            let mut ncw = NcwReader::read(file)?;
+            let samples = ncw.decode_samples_f32()?;
+
+            let wav: Vec<f32> = read_wav_data(&format!("tests/data/{name}.wav"))?
+                .chunks_exact(4)
+                .map(|bytes| f32::from_le_bytes(bytes.try_into().unwrap()))
+                .collect();
+
+            assert_eq!(samples.len(), wav.len());
+            assert!(
+                samples
+                    .iter()
+                    .zip(&wav)
+                    .all(|(a, b)| a.to_bits() == b.to_bits()),
+                "{name} does not match wav"
+            );
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_decode_samples_f32_pcm() -> Result<(), Error> {
+        let file = File::open("tests/data/16-bit-stereo.ncw")?;
+        let mut ncw = NcwReader::read(file)?;
+        let samples = ncw.decode_samples()?;
+        let floats = ncw.decode_samples_f32()?;
+
+        assert_eq!(floats.len(), samples.len());
+        assert_eq!(floats[0], samples[0] as f32 / 32768.0);
+        assert!(floats.iter().all(|s| (-1.0..1.0).contains(s)));
+        Ok(())
+    }
 }
@@ -0,0 +1,254 @@
+//! Zero-copy reading of NCW files held in memory.
+
+#[cfg(feature = "alloc")]
+use alloc::{vec, vec::Vec};
+
+use crate::decode::{decode_block_with, interleave_channel, NcwHeader};
+
+type Error = crate::NcwError;
+
+/// Reader over a complete NCW file in memory, such as a memory map. The header and block offset
+/// table are validated once by [`NcwSlice::new`], after which blocks are decoded straight from
+/// the borrowed bytes.
+#[derive(Debug, Clone)]
+pub struct NcwSlice<'a> {
+    pub header: NcwHeader,
+    /// Reject blocks that set flags this crate does not know with [`Error::UnknownBlockFlags`],
+    /// rather than ignoring those flags.
+    pub strict_flags: bool,
+    data: &'a [u8],
+    num_blocks: usize,
+}
+
+impl<'a> NcwSlice<'a> {
+    pub fn new(data: &'a [u8]) -> Result<Self, Error> {
+        let header = NcwHeader::parse(data)?;
+        let num_blocks = header.num_blocks()?;
+
+        // Summed as u64, as the offsets and sizes of the header can overflow a 32-bit usize
+        let table_end = header.blocks_offset as u64 + num_blocks as u64 * 4;
+        let data_end = header.data_offset as u64 + header.data_size as u64;
+        for end in [table_end, data_end] {
+            if (data.len() as u64) < end {
+                return Err(Error::ReadError(usize::try_from(end).unwrap_or(usize::MAX)));
+            }
+        }
+
+        let slice = Self {
+            header,
+            strict_flags: false,
+            data: &data[..data_end as usize],
+            num_blocks,
+        };
+        for block in 0..num_blocks {
+            slice.header.block_start(block, slice.block_offset(block))?;
+        }
+
+        Ok(slice)
+    }
+
+    pub fn num_blocks(&self) -> usize {
+        self.num_blocks
+    }
+
+    /// Decode block `index` into `out` as interleaved 32-bit PCM samples, without allocating.
+    /// Returns the number of frames written, which is 0 past the last block. `out` must hold the
+    /// whole block, i.e. 512 frames for all but the final block.
+    pub fn decode_block_into(&self, index: usize, out: &mut [i32]) -> Result<usize, Error> {
+        if index >= self.num_blocks {
+            return Ok(0);
+        }
+
+        let channels = self.header.channels as usize;
+        let frames = self.header.block_frames(index);
+        if out.len() < frames * channels {
+            return Err(Error::InvalidSampleCount(out.len()));
+        }
+
+        let start = self.header.data_offset as usize + self.block_offset(index) as usize;
+        let out = &mut out[..frames * channels];
+        decode_block_with(
+            &self.data[start..],
+            &self.header,
+            self.strict_flags,
+            |channel, samples, _| {
+                interleave_channel(samples.iter().copied(), channel, channels, out)
+            },
+        )
+        .map_err(|e| e.with_block(index, start as u64))?;
+
+        Ok(frames)
+    }
+
+    /// Decode block `index` into interleaved 32-bit PCM samples.
+    #[cfg(feature = "alloc")]
+    pub fn decode_block(&self, index: usize) -> Result<Vec<i32>, Error> {
+        let mut samples = vec![0; self.header.block_frames(index) * self.header.channels as usize];
+        self.decode_block_into(index, &mut samples)?;
+        Ok(samples)
+    }
+
+    /// Decode all blocks into contiguous 32-bit PCM samples.
+    #[cfg(feature = "alloc")]
+    pub fn decode_samples(&self) -> Result<Vec<i32>, Error> {
+        let channels = self.header.channels as usize;
+        let mut samples = vec![0; self.header.total_samples(self.num_blocks)?];
+
+        let mut start = 0;
+        for index in 0..self.num_blocks {
+            start += self.decode_block_into(index, &mut samples[start..])? * channels;
+        }
+
+        Ok(samples)
+    }
+
+    /// Offset of block `index` relative to the data section.
+    fn block_offset(&self, index: usize) -> u32 {
+        let at = self.header.blocks_offset as usize + index * 4;
+        u32::from_le_bytes(self.data[at..at + 4].try_into().unwrap())
+    }
+}
+
+/// An NCW file opened as a read-only memory map, decoded without copying through
+/// [`NcwSlice`].
+#[cfg(feature = "memmap2")]
+#[derive(Debug)]
+pub struct NcwMmap {
+    mmap: memmap2::Mmap,
+    header: NcwHeader,
+    num_blocks: usize,
+}
+
+#[cfg(feature = "memmap2")]
+impl NcwMmap {
+    /// Memory map the file at `path` and validate its header and block offset table.
+    ///
+    /// # Safety
+    ///
+    /// The file must not be modified or truncated while it is mapped, see
+    /// [`memmap2::Mmap::map`].
+    pub unsafe fn open<P: AsRef<std::path::Path>>(path: P) -> Result<Self, Error> {
+        let file = std::fs::File::open(path)?;
+        // SAFETY: upheld by the caller
+        let mmap = unsafe { memmap2::Mmap::map(&file)? };
+        let NcwSlice {
+            header, num_blocks, ..
+        } = NcwSlice::new(&mmap)?;
+
+        Ok(Self {
+            mmap,
+            header,
+            num_blocks,
+        })
+    }
+
+    pub fn slice(&self) -> NcwSlice<'_> {
+        let data_end = self.header.data_offset as usize + self.header.data_size as usize;
+        NcwSlice {
+            header: self.header.clone(),
+            strict_flags: false,
+            data: &self.mmap[..data_end],
+            num_blocks: self.num_blocks,
+        }
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+    use crate::decode::MAX_SAMPLES_PER_BLOCK;
+    use crate::test_util::{stereo_with_corrupt_num_samples, STEREO_SECOND_BLOCK};
+    use crate::NcwReader;
+    use std::fs::File;
+
+    #[test]
+    fn test_slice_matches_reader() -> Result<(), Error> {
+        for name in [
+            "16-bit-mono",
+            "16-bit-stereo",
+            "24-bit-stereo",
+            "16-bit-stereo-midside",
+            "32-bit-mono-float",
+            "testfile-onezero-16-bit-stereo-multiblock",
+        ] {
+            let data = std::fs::read(format!("tests/data/{name}.ncw"))?;
+            let slice = NcwSlice::new(&data)?;
+
+            let mut ncw = NcwReader::read(File::open(format!("tests/data/{name}.ncw"))?)?;
+            assert_eq!(slice.num_blocks(), ncw.block_offsets.len());
+            assert_eq!(slice.decode_samples()?, ncw.decode_samples()?, "{name}");
+
+            let mut out = vec![0; slice.header.channels as usize * MAX_SAMPLES_PER_BLOCK];
+            for (index, expected) in ncw.blocks().enumerate() {
+                let expected = expected?;
+                let frames = slice.decode_block_into(index, &mut out)?;
+                assert_eq!(frames * slice.header.channels as usize, expected.len());
+                assert_eq!(out[..expected.len()], expected[..]);
+                assert_eq!(slice.decode_block(index)?, expected);
+            }
+            assert_eq!(slice.decode_block_into(slice.num_blocks(), &mut out)?, 0);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_slice_errors() -> Result<(), Error> {
+        let data = std::fs::read("tests/data/16-bit-stereo.ncw")?;
+        let header = NcwHeader::parse(&data)?;
+
+        assert!(matches!(
+            NcwSlice::new(&data[..data.len() - 1]),
+            Err(Error::ReadError(_))
+        ));
+
+        // A data section ending past 4 GiB is reported rather than wrapping around
+        let mut corrupt = data.clone();
+        corrupt[28..32].copy_from_slice(&u32::MAX.to_le_bytes());
+        let end = header.data_offset as u64 + u32::MAX as u64;
+        assert!(matches!(
+            NcwSlice::new(&corrupt),
+            Err(Error::ReadError(len)) if len as u64 == end.min(usize::MAX as u64)
+        ));
+
+        let mut corrupt = data.clone();
+        corrupt[header.blocks_offset as usize + 4..][..4].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(matches!(
+            NcwSlice::new(&corrupt),
+            Err(Error::BlockOffsetOutOfRange {
+                block: 1,
+                offset: u32::MAX
+            })
+        ));
+
+        // Offsets are relative to the start of the file
+        let mut corrupt = data.clone();
+        corrupt[STEREO_SECOND_BLOCK] = 0;
+        let slice = NcwSlice::new(&corrupt)?;
+        assert!(slice.decode_block(0).is_ok());
+        assert!(matches!(
+            slice.decode_block(1),
+            Err(Error::InvalidBlockMagic { block: 1, offset })
+                if offset == STEREO_SECOND_BLOCK as u64
+        ));
+
+        let mut out = [0; 100];
+        assert!(matches!(
+            slice.decode_block_into(0, &mut out),
+            Err(Error::InvalidSampleCount(100))
+        ));
+
+        let (corrupt, total) = stereo_with_corrupt_num_samples()?;
+        assert_eq!(NcwSlice::new(&corrupt)?.decode_samples()?.len(), total);
+        Ok(())
+    }
+
+    #[cfg(feature = "memmap2")]
+    #[test]
+    fn test_mmap() -> Result<(), Error> {
+        // SAFETY: test fixtures are not modified while mapped
+        let mmap = unsafe { NcwMmap::open("tests/data/24-bit-stereo.ncw")? };
+        let mut ncw = NcwReader::read(File::open("tests/data/24-bit-stereo.ncw")?)?;
+        assert_eq!(mmap.slice().decode_samples()?, ncw.decode_samples()?);
+        Ok(())
+    }
+}
@@ -0,0 +1,60 @@
+//! Helpers shared by the tests of several modules.
+
+use std::fs::File;
+use std::io::{Seek, SeekFrom};
+
+use crate::decode::{NcwHeader, BLOCK_HEADER_SIZE, MAX_SAMPLES_PER_BLOCK};
+use crate::read_bytes::ReadBytesExt;
+use crate::NcwError as Error;
+
+/// Offset of the second block of 16-bit-stereo.ncw: its data section starts at 1152 and the
+/// first block takes 1504 bytes.
+pub(crate) const STEREO_SECOND_BLOCK: usize = 1152 + 1504;
+
+/// Offset of the right channel of the first block of unknown-flag.ncw: its data section starts
+/// at 1204 and the left channel holds 23-bit deltas.
+pub(crate) const UNKNOWN_FLAG_RIGHT_CHANNEL: usize = 1204 + BLOCK_HEADER_SIZE + 23 * 64;
+
+/// unknown-flag.ncw with an unknown flag (`0b100`) also set on the right channel of the first
+/// block, at [`UNKNOWN_FLAG_RIGHT_CHANNEL`].
+pub(crate) fn unknown_flag_with_extra_flag() -> Result<Vec<u8>, Error> {
+    let mut data = std::fs::read("tests/data/unknown-flag.ncw")?;
+    data[UNKNOWN_FLAG_RIGHT_CHANNEL + 10] |= 0b100;
+    Ok(data)
+}
+
+/// 16-bit-stereo.ncw with a header claiming far more frames than its block table holds, along
+/// with the number of samples the table does hold. A corrupt count like this must not size an
+/// allocation on its own.
+pub(crate) fn stereo_with_corrupt_num_samples() -> Result<(Vec<u8>, usize), Error> {
+    let mut data = std::fs::read("tests/data/16-bit-stereo.ncw")?;
+    data[16..20].copy_from_slice(&u32::MAX.to_le_bytes());
+    let total = NcwHeader::parse(&data)?.num_blocks()? * MAX_SAMPLES_PER_BLOCK * 2;
+    Ok((data, total))
+}
+
+/// Decode a wav's integer samples with hound.
+pub(crate) fn read_wav(path: &str) -> Vec<i32> {
+    let mut wav = hound::WavReader::open(path).unwrap();
+    wav.samples::<i32>().map(Result::unwrap).collect()
+}
+
+/// Read the raw chunk `id` of a wav, for files with headers hound cannot parse.
+pub(crate) fn read_wav_chunk(path: &str, id: &[u8; 4]) -> Result<Vec<u8>, Error> {
+    let mut file = File::open(path)?;
+    file.seek(SeekFrom::Start(12))?;
+
+    loop {
+        let chunk_id = file.read_bytes(4)?;
+        let size = file.read_u32_le()? as usize;
+        if chunk_id == id {
+            return file.read_bytes(size);
+        }
+        file.seek(SeekFrom::Current((size + size % 2) as i64))?;
+    }
+}
+
+/// Read the raw `data` chunk of a wav.
+pub(crate) fn read_wav_data(path: &str) -> Result<Vec<u8>, Error> {
+    read_wav_chunk(path, b"data")
+}
@@ -0,0 +1,294 @@
+//! Unpacking of LSB-first bit packed signed values, with a kernel specialised for every width.
+
+/// Call `unpack_width::<BITS, $avx2>` for the runtime width `$bits`.
+macro_rules! kernels {
+    ($bits:expr, $avx2:literal, $data:expr, $out:expr) => {
+        kernels!(@ $bits, $avx2, $data, $out,
+            1 2 3 4 5 6 7 8 9 10 11 12 13 14 15 16 17 18 19 20 21 22 23 24 25 26 27 28 29 30 31 32)
+    };
+    (@ $bits:expr, $avx2:literal, $data:expr, $out:expr, $($width:literal)*) => {
+        match $bits {
+            $($width => unpack_width::<$width, $avx2>($data, $out),)*
+            bits => panic!("unsupported packed width {bits}"),
+        }
+    };
+}
+
+/// Unpack `out.len()` signed `bits` wide values from `data` into `out`.
+///
+/// `data` must hold at least `out.len() * bits` bits and `bits` must be in `1..=32`.
+pub(crate) fn unpack_signed_i32(data: &[u8], bits: usize, out: &mut [i32]) {
+    debug_assert!(data.len() * 8 >= out.len() * bits);
+
+    #[cfg(target_arch = "x86_64")]
+    if bits <= 25 && avx2::is_available() {
+        // SAFETY: avx2 support has just been checked
+        unsafe { avx2::unpack_signed_i32(data, bits, out) };
+        return;
+    }
+
+    unpack_portable(data, bits, out);
+}
+
+fn unpack_portable(data: &[u8], bits: usize, out: &mut [i32]) {
+    kernels!(bits, false, data, out);
+}
+
+/// Every group of 8 values spans exactly `BITS` bytes, so the position of each value within a
+/// group is known at compile time. With `AVX2`, full groups of up to 25 bit values are unpacked
+/// with AVX2, which the caller must have checked is available.
+#[inline(always)]
+fn unpack_width<const BITS: usize, const AVX2: bool>(data: &[u8], out: &mut [i32]) {
+    // Padded so a whole word can be read at the last value's byte offset
+    let mut group = [0_u8; 40];
+
+    for (i, values) in out.chunks_mut(8).enumerate() {
+        let bytes = &data[i * BITS..];
+
+        #[cfg(target_arch = "x86_64")]
+        if AVX2 && BITS <= 25 && values.len() == 8 && bytes.len() >= avx2::group_len::<BITS>() {
+            // SAFETY: `AVX2` is only set by `avx2::unpack_signed_i32`, whose caller checked
+            // avx2 support
+            unsafe { avx2::unpack_group::<BITS>(bytes, values) };
+            continue;
+        }
+
+        if bytes.len() >= BITS {
+            group[..BITS].copy_from_slice(&bytes[..BITS]);
+        } else {
+            group = [0; 40];
+            group[..bytes.len()].copy_from_slice(bytes);
+        }
+
+        for (j, value) in values.iter_mut().enumerate() {
+            let bit = j * BITS;
+            let word = u64::from_le_bytes(group[bit / 8..bit / 8 + 8].try_into().unwrap());
+            // Sign extend from `BITS` bits
+            *value = (((word >> (bit % 8)) << (64 - BITS)) as i64 >> (64 - BITS)) as i32;
+        }
+    }
+}
+
+#[cfg(target_arch = "x86_64")]
+mod avx2 {
+    use core::arch::x86_64::*;
+
+    use super::{unpack_width, Group};
+
+    /// Whether the CPU supports AVX2, detected at runtime with `std` and only known at compile
+    /// time without it.
+    #[inline]
+    pub(super) fn is_available() -> bool {
+        #[cfg(feature = "std")]
+        return std::is_x86_feature_detected!("avx2");
+        #[cfg(not(feature = "std"))]
+        return cfg!(target_feature = "avx2");
+    }
+
+    /// [`super::unpack_signed_i32`] compiled with AVX2 enabled. The CPU must support AVX2.
+    #[target_feature(enable = "avx2")]
+    pub(super) fn unpack_signed_i32(data: &[u8], bits: usize, out: &mut [i32]) {
+        kernels!(bits, true, data, out);
+    }
+
+    /// Bytes read by [`unpack_group`], which can extend past the group into the next one.
+    pub(super) const fn group_len<const BITS: usize>() -> usize {
+        Group::<BITS>::HIGH + 16
+    }
+
+    /// Unpack a full group of 8 values at once, straight from `bytes` so the loads are not
+    /// stalled waiting on a copy. Each value, shifted by at most 7 bits, fits in the 32-bit word
+    /// at its byte offset for widths up to 25, and the words of the last 4 values lie within the
+    /// 16 bytes from the byte holding the fifth, so each 128-bit lane gathers its words with a
+    /// byte shuffle.
+    #[target_feature(enable = "avx2")]
+    pub(super) fn unpack_group<const BITS: usize>(bytes: &[u8], out: &mut [i32]) {
+        assert!(BITS <= 25 && bytes.len() >= group_len::<BITS>() && out.len() == 8);
+
+        let high = Group::<BITS>::HIGH;
+        let indices = Group::<BITS>::INDICES;
+        let shifts = Group::<BITS>::SHIFTS;
+
+        // SAFETY: both 16 byte loads lie within `bytes` and `out` holds 8 values
+        unsafe {
+            let bytes = _mm256_set_m128i(
+                _mm_loadu_si128(bytes[high..].as_ptr() as *const __m128i),
+                _mm_loadu_si128(bytes.as_ptr() as *const __m128i),
+            );
+            let indices = _mm256_loadu_si256(indices.as_ptr() as *const __m256i);
+            let shifts = _mm256_loadu_si256(shifts.as_ptr() as *const __m256i);
+            let extend = _mm_cvtsi32_si128(32 - BITS as i32);
+
+            let words = _mm256_shuffle_epi8(bytes, indices);
+            let values = _mm256_srlv_epi32(words, shifts);
+            let values = _mm256_sra_epi32(_mm256_sll_epi32(values, extend), extend);
+            _mm256_storeu_si256(out.as_mut_ptr() as *mut __m256i, values);
+        }
+    }
+}
+
+/// Shuffle indices and shifts of a group of 8 `BITS` wide values, see [`unpack_group`].
+struct Group<const BITS: usize>;
+
+impl<const BITS: usize> Group<BITS> {
+    /// Byte holding the fifth value, the start of the second lane.
+    const HIGH: usize = 4 * BITS / 8;
+
+    /// Bytes of each value's word, relative to the start of its lane.
+    const INDICES: [i8; 32] = {
+        let mut indices = [0; 32];
+        let mut i = 0;
+        while i < 32 {
+            let (j, k) = (i / 4, i % 4);
+            let base = if j < 4 { 0 } else { Self::HIGH };
+            indices[i] = (j * BITS / 8 - base + k) as i8;
+            i += 1;
+        }
+        indices
+    };
+
+    /// Bit offset of each value within its word.
+    const SHIFTS: [i32; 8] = {
+        let mut shifts = [0; 8];
+        let mut j = 0;
+        while j < 8 {
+            shifts[j] = (j * BITS % 8) as i32;
+            j += 1;
+        }
+        shifts
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The original byte at a time unpacker, kept as a reference.
+    fn read_packed_values_i32(data: &[u8], precision_in_bits: usize) -> Vec<i32> {
+        let mut values: Vec<i32> = Vec::new();
+        let mut bit_accumulator: u64 = 0;
+        let mut bits_in_accumulator: usize = 0;
+        let mut byte_index = 0;
+
+        while byte_index < data.len() {
+            // Accumulate more bits
+            bit_accumulator |= (data[byte_index] as u64) << bits_in_accumulator;
+            bits_in_accumulator += 8;
+            byte_index += 1;
+
+            // Extract values as long as enough bits are available
+            while bits_in_accumulator >= precision_in_bits {
+                let mut value = bit_accumulator & ((1 << precision_in_bits) - 1);
+                if value & (1 << (precision_in_bits - 1)) != 0 {
+                    value |= !0 << precision_in_bits;
+                }
+                values.push(value as i32);
+
+                // Remove used bits
+                bit_accumulator >>= precision_in_bits;
+                bits_in_accumulator -= precision_in_bits;
+            }
+        }
+
+        values
+    }
+
+    type Kernel = fn(&[u8], usize, &mut [i32]);
+
+    /// Every kernel the running CPU supports, so the AVX2 path is tested without compiling for it.
+    fn kernels() -> Vec<(&'static str, Kernel)> {
+        let mut kernels: Vec<(&'static str, Kernel)> = vec![("portable", unpack_portable)];
+        #[cfg(target_arch = "x86_64")]
+        if avx2::is_available() {
+            // SAFETY: avx2 support has just been checked
+            kernels.push(("avx2", |data, bits, out| unsafe {
+                avx2::unpack_signed_i32(data, bits, out)
+            }));
+        }
+        kernels
+    }
+
+    fn random_bytes(len: usize, seed: u32) -> Vec<u8> {
+        let mut state = seed;
+        (0..len)
+            .map(|_| {
+                state = state.wrapping_mul(1664525).wrapping_add(1013904223);
+                (state >> 24) as u8
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_unpack_matches_reference() {
+        for bits in 1..=32 {
+            for data in [
+                random_bytes(bits * 64, bits as u32),
+                vec![0xFF; bits * 64],
+                vec![0x00; bits * 64],
+            ] {
+                let expected = read_packed_values_i32(&data, bits);
+                assert_eq!(expected.len(), 512);
+
+                for (kernel, unpack) in kernels() {
+                    let mut out = [0; 512];
+                    unpack(&data, bits, &mut out);
+                    assert_eq!(out[..], expected[..], "{kernel} width {bits}");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_unpack_partial() {
+        // Counts that end mid group, from data holding no more bytes than needed
+        for bits in 1..=32 {
+            for len in [1_usize, 7, 9, 13, 100] {
+                let data = random_bytes((len * bits).div_ceil(8), len as u32);
+                let expected = read_packed_values_i32(&data, bits);
+
+                for (kernel, unpack) in kernels() {
+                    let mut out = vec![0; len];
+                    unpack(&data, bits, &mut out);
+                    assert_eq!(
+                        out[..],
+                        expected[..len],
+                        "{kernel} width {bits}, {len} values"
+                    );
+                }
+            }
+        }
+    }
+
+    /// Throughput of each kernel against the reference unpacker, run with
+    /// `cargo test --release unpack::tests::bench_unpack -- --ignored --nocapture`.
+    #[test]
+    #[ignore]
+    fn bench_unpack() {
+        const ITERATIONS: u32 = 20_000;
+
+        for bits in [4, 8, 12, 16, 20, 24, 28, 32] {
+            let data = random_bytes(bits * 64, bits as u32);
+            let time = |unpack: &dyn Fn(&[u8], &mut [i32])| {
+                let mut out = [0; 512];
+                let start = std::time::Instant::now();
+                for _ in 0..ITERATIONS {
+                    unpack(std::hint::black_box(&data), &mut out);
+                    std::hint::black_box(&out);
+                }
+                start.elapsed() / ITERATIONS
+            };
+
+            let reference = time(&|data, out| {
+                out.copy_from_slice(&read_packed_values_i32(data, bits));
+            });
+            print!("width {bits:2}: reference {reference:?}");
+            for (kernel, unpack) in kernels() {
+                print!(
+                    ", {kernel} {:?}",
+                    time(&|data, out| unpack(data, bits, out))
+                );
+            }
+            println!(" per 512 values");
+        }
+    }
+}
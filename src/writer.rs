@@ -0,0 +1,1005 @@
+use std::io::{Read, Seek, SeekFrom, Write};
+
+use crate::decode::{
+    BlockFlags, BlockHeader, NcwHeader, NcwVersion, BLOCK_HEADER_SIZE, HEADER_SIZE,
+    MAX_SAMPLES_PER_BLOCK,
+};
+
+type Error = crate::NcwError;
+
+#[derive(Debug)]
+pub struct NcwWriter<W> {
+    pub writer: W,
+    pub header: NcwHeader,
+    pub compression: Compression,
+    /// Frames of the incomplete block passed to `write_frames`.
+    pending: Vec<i32>,
+    /// Offsets of the blocks written so far by `write_frames`, including the end offset.
+    block_offsets: Vec<u32>,
+    /// Stream position of the file being written by `write_frames`.
+    start: Option<u64>,
+}
+
+/// Strategy used to choose how each block is stored.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Compression {
+    /// Store each block using whichever of delta, truncation or raw storage is smallest.
+    #[default]
+    Smallest,
+    /// Approximate Native Instruments' encoder with rules fitted to the shipped fixtures: delta
+    /// encoding with at least 2 bits, and full width truncation when deltas need
+    /// `bits_per_sample` or more. None of the fixtures contain raw or mid/side blocks, so how
+    /// Native Instruments' encoder chooses those is unknown.
+    NativeInstruments,
+}
+
+impl<W: Write + Seek> NcwWriter<W> {
+    pub fn new(
+        writer: W,
+        channels: u16,
+        bits_per_sample: u16,
+        sample_rate: u32,
+    ) -> Result<Self, Error> {
+        if channels == 0 {
+            return Err(Error::InvalidChannelCount(channels));
+        }
+        if ![8, 16, 24, 32].contains(&bits_per_sample) {
+            return Err(Error::UnsupportedBitDepth(bits_per_sample));
+        }
+
+        Ok(Self {
+            writer,
+            header: NcwHeader {
+                version: NcwVersion::default(),
+                channels,
+                bits_per_sample,
+                sample_rate,
+                num_samples: 0,
+                blocks_offset: HEADER_SIZE as u32,
+                data_offset: HEADER_SIZE as u32,
+                data_size: 0,
+                format: 0,
+                reserved: [0; 84],
+            },
+            compression: Compression::default(),
+            pending: Vec::new(),
+            block_offsets: Vec::new(),
+            start: None,
+        })
+    }
+
+    /// Encode interleaved 32-bit PCM samples and write the complete file.
+    pub fn write_samples(&mut self, samples: &[i32]) -> Result<(), Error> {
+        self.check_samples(samples)?;
+
+        self.header.format = 0;
+        let encoder = self.encoder(BlockFlags::default());
+        self.write_blocks(
+            samples.len(),
+            samples
+                .chunks(encoder.block_len())
+                .map(|frames| encoder.encode(frames)),
+        )
+    }
+
+    /// Encode interleaved 32-bit float samples and write the complete file. The samples are
+    /// stored losslessly as their bit patterns, which requires a 32-bit writer.
+    pub fn write_samples_f32(&mut self, samples: &[f32]) -> Result<(), Error> {
+        let samples = self.float_bits(samples)?;
+
+        self.header.format = 1;
+        let encoder = self.encoder(BlockFlags::FLOAT);
+        self.write_blocks(
+            samples.len(),
+            samples
+                .chunks(encoder.block_len())
+                .map(|frames| encoder.encode(frames)),
+        )
+    }
+
+    /// Same as `write_samples`, compressing blocks in parallel. The output is identical.
+    #[cfg(feature = "rayon")]
+    pub fn write_samples_parallel(&mut self, samples: &[i32]) -> Result<(), Error> {
+        use rayon::prelude::*;
+
+        self.check_samples(samples)?;
+
+        self.header.format = 0;
+        let encoder = self.encoder(BlockFlags::default());
+        let blocks: Vec<_> = samples
+            .par_chunks(encoder.block_len())
+            .map(|frames| encoder.encode(frames))
+            .collect();
+        self.write_blocks(samples.len(), blocks)
+    }
+
+    /// Same as `write_samples_f32`, compressing blocks in parallel. The output is identical.
+    #[cfg(feature = "rayon")]
+    pub fn write_samples_f32_parallel(&mut self, samples: &[f32]) -> Result<(), Error> {
+        use rayon::prelude::*;
+
+        let samples = self.float_bits(samples)?;
+
+        self.header.format = 1;
+        let encoder = self.encoder(BlockFlags::FLOAT);
+        let blocks: Vec<_> = samples
+            .par_chunks(encoder.block_len())
+            .map(|frames| encoder.encode(frames))
+            .collect();
+        self.write_blocks(samples.len(), blocks)
+    }
+
+    /// Write the header, block offsets and the encoded blocks of `num_samples` samples.
+    fn write_blocks(
+        &mut self,
+        num_samples: usize,
+        blocks: impl IntoIterator<Item = EncodedBlock>,
+    ) -> Result<(), Error> {
+        let num_frames = num_samples / self.header.channels as usize;
+        let num_blocks = num_frames.div_ceil(MAX_SAMPLES_PER_BLOCK);
+
+        self.header.num_samples = u32::try_from(num_frames).map_err(|_| Error::FileTooLarge)?;
+        self.header.blocks_offset = HEADER_SIZE as u32;
+        self.header.data_offset =
+            u32::try_from(HEADER_SIZE + (num_blocks + 1) * 4).map_err(|_| Error::FileTooLarge)?;
+
+        // Write the blocks first, then seek back for the header and block offsets
+        let start = self.writer.stream_position()?;
+        self.writer
+            .seek(SeekFrom::Start(start + self.header.data_offset as u64))?;
+
+        let mut block_offsets = Vec::with_capacity(num_blocks + 1);
+        let mut offset = 0;
+        for block in blocks {
+            block_offsets.push(offset);
+            offset = self.write_block(offset, block)?;
+        }
+        block_offsets.push(offset);
+        self.header.data_size = offset;
+
+        self.write_header(start, &block_offsets)
+    }
+
+    /// Write the header and block offsets at `start`, leaving the stream at the end of the data.
+    fn write_header(&mut self, start: u64, block_offsets: &[u32]) -> Result<(), Error> {
+        self.writer.seek(SeekFrom::Start(start))?;
+        self.header.write(&mut self.writer)?;
+        for block_offset in block_offsets {
+            self.writer.write_all(&block_offset.to_le_bytes())?;
+        }
+
+        self.writer.seek(SeekFrom::Start(
+            start + self.header.data_offset as u64 + self.header.data_size as u64,
+        ))?;
+
+        Ok(())
+    }
+
+    /// Check that `samples` holds whole frames that fit the bit depth.
+    fn check_samples(&self, samples: &[i32]) -> Result<(), Error> {
+        let channels = self.header.channels as usize;
+        if samples.len() % channels != 0 {
+            return Err(Error::InvalidSampleCount(samples.len()));
+        }
+
+        let bits = self.header.bits_per_sample as u32;
+        let (min, max) = (i32::MIN >> (32 - bits), i32::MAX >> (32 - bits));
+        if let Some(sample) = samples.iter().find(|s| !(min..=max).contains(*s)) {
+            return Err(Error::SampleOutOfRange(*sample));
+        }
+
+        Ok(())
+    }
+
+    /// Check that `samples` holds whole frames for a 32-bit writer and return their bit patterns.
+    fn float_bits(&self, samples: &[f32]) -> Result<Vec<i32>, Error> {
+        let channels = self.header.channels as usize;
+        if samples.len() % channels != 0 {
+            return Err(Error::InvalidSampleCount(samples.len()));
+        }
+        if self.header.bits_per_sample != 32 {
+            return Err(Error::UnsupportedBitDepth(self.header.bits_per_sample));
+        }
+
+        Ok(samples.iter().map(|s| s.to_bits() as i32).collect())
+    }
+
+    fn encoder(&self, flags: BlockFlags) -> BlockEncoder {
+        BlockEncoder {
+            channels: self.header.channels as usize,
+            bits_per_sample: self.header.bits_per_sample as usize,
+            flags,
+            compression: self.compression,
+        }
+    }
+
+    /// Write the channel blocks of a single encoded block at `offset` into the data section,
+    /// returning the offset of the next block. Fails without writing anything when the block
+    /// would end past the 4 GiB a block offset can address.
+    fn write_block(&mut self, offset: u32, block: EncodedBlock) -> Result<u32, Error> {
+        let size: usize = block
+            .iter()
+            .map(|(_, data)| BLOCK_HEADER_SIZE + data.len())
+            .sum();
+        let end = u32::try_from(size)
+            .ok()
+            .and_then(|size| offset.checked_add(size))
+            .ok_or(Error::FileTooLarge)?;
+
+        for (block_header, data) in block {
+            block_header.write(&mut self.writer)?;
+            self.writer.write_all(&data)?;
+        }
+
+        Ok(end)
+    }
+}
+
+impl<W: Read + Write + Seek> NcwWriter<W> {
+    /// Encode interleaved 32-bit PCM frames as they arrive, without knowing the final length.
+    /// Only the current block is buffered; call `finalize` once all frames are written.
+    ///
+    /// The writer must also be readable, as `finalize` reads back everything written so far. A
+    /// `File` has to be opened with `OpenOptions::new().read(true).write(true)`, since
+    /// `File::create` is write-only. The first call checks this, so an unreadable writer fails
+    /// before any audio has been streamed.
+    pub fn write_frames(&mut self, frames: &[i32]) -> Result<(), Error> {
+        self.check_samples(frames)?;
+
+        // Blocks are written straight after the header, and moved past the offset table once
+        // its size is known
+        if self.start.is_none() {
+            let start = self.writer.stream_position()?;
+            let _probe = self.writer.read(&mut [0; 1])?;
+            self.writer
+                .seek(SeekFrom::Start(start + HEADER_SIZE as u64))?;
+            self.start = Some(start);
+            self.block_offsets = vec![0];
+            self.header.num_samples = 0;
+        }
+
+        let block_len = MAX_SAMPLES_PER_BLOCK * self.header.channels as usize;
+        let mut frames = frames;
+        while !frames.is_empty() {
+            let len = (block_len - self.pending.len()).min(frames.len());
+            self.pending.extend_from_slice(&frames[..len]);
+            frames = &frames[len..];
+
+            if self.pending.len() == block_len {
+                self.flush_pending()?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Write the final partial block, then patch the header and block offset table for the
+    /// frames passed to `write_frames`.
+    ///
+    /// The size of the block offset table is only known at this point, so the whole data section
+    /// is read back and moved forward to make room for it, copying every block once.
+    pub fn finalize(&mut self) -> Result<(), Error> {
+        if !self.pending.is_empty() {
+            self.flush_pending()?;
+        }
+
+        let start = match self.start.take() {
+            Some(start) => start,
+            None => {
+                self.header.num_samples = 0;
+                self.writer.stream_position()?
+            }
+        };
+        let mut block_offsets = std::mem::take(&mut self.block_offsets);
+        if block_offsets.is_empty() {
+            block_offsets.push(0);
+        }
+
+        self.header.format = 0;
+        self.header.blocks_offset = HEADER_SIZE as u32;
+        self.header.data_offset = (HEADER_SIZE + block_offsets.len() * 4) as u32;
+        self.header.data_size = block_offsets[block_offsets.len() - 1];
+
+        self.shift_forward(
+            start + HEADER_SIZE as u64,
+            start + self.header.data_offset as u64,
+            self.header.data_size as u64,
+        )?;
+
+        self.write_header(start, &block_offsets)
+    }
+
+    /// Encode and write the buffered frames as one block.
+    fn flush_pending(&mut self) -> Result<(), Error> {
+        let frames = std::mem::take(&mut self.pending);
+        let num_samples = self
+            .header
+            .num_samples
+            .checked_add((frames.len() / self.header.channels as usize) as u32)
+            .ok_or(Error::FileTooLarge)?;
+
+        let block = self.encoder(BlockFlags::default()).encode(&frames);
+        let offset = self.block_offsets[self.block_offsets.len() - 1];
+        let end = self.write_block(offset, block)?;
+
+        self.block_offsets.push(end);
+        self.header.num_samples = num_samples;
+
+        self.pending = frames;
+        self.pending.clear();
+        Ok(())
+    }
+
+    /// Move `len` bytes at `from` forward to `to`, copying from the end as the ranges overlap.
+    fn shift_forward(&mut self, from: u64, to: u64, len: u64) -> Result<(), Error> {
+        let mut buffer = vec![0; 64 * 1024];
+        let mut remaining = len;
+        while remaining > 0 {
+            let chunk_len = remaining.min(buffer.len() as u64);
+            remaining -= chunk_len;
+
+            let chunk = &mut buffer[..chunk_len as usize];
+            self.writer.seek(SeekFrom::Start(from + remaining))?;
+            self.writer.read_exact(chunk)?;
+            self.writer.seek(SeekFrom::Start(to + remaining))?;
+            self.writer.write_all(chunk)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// The header and payload of every channel in a block.
+type EncodedBlock = Vec<(BlockHeader, Vec<u8>)>;
+
+/// Settings needed to encode a block independently of the writer.
+#[derive(Clone, Copy)]
+struct BlockEncoder {
+    channels: usize,
+    bits_per_sample: usize,
+    flags: BlockFlags,
+    compression: Compression,
+}
+
+impl BlockEncoder {
+    /// Number of interleaved samples in a full block.
+    fn block_len(&self) -> usize {
+        MAX_SAMPLES_PER_BLOCK * self.channels
+    }
+
+    /// Encode a single block of interleaved frames.
+    fn encode(&self, frames: &[i32]) -> EncodedBlock {
+        let (bits_per_sample, flags) = (self.bits_per_sample, self.flags);
+
+        // Partial blocks are padded with silence
+        let mut samples = vec![[0; MAX_SAMPLES_PER_BLOCK]; self.channels];
+        for (i, frame) in frames.chunks_exact(self.channels).enumerate() {
+            for (channel, sample) in samples.iter_mut().zip(frame) {
+                channel[i] = *sample;
+            }
+        }
+
+        // Stereo is always stored as left/right, as the decoder's mid/side convention has not been
+        // confirmed against Kontakt
+        samples
+            .iter()
+            .map(|channel| encode_block_i32(channel, bits_per_sample, flags, self.compression))
+            .collect()
+    }
+}
+
+/// Encode a block with the storage mode chosen by `compression`.
+fn encode_block_i32(
+    samples: &[i32],
+    bits_per_sample: usize,
+    flags: BlockFlags,
+    compression: Compression,
+) -> (BlockHeader, Vec<u8>) {
+    let deltas = block_deltas(samples);
+    let delta_bits = signed_bit_width(&deltas);
+
+    if compression == Compression::NativeInstruments {
+        let delta_bits = delta_bits.max(2);
+        return if delta_bits >= bits_per_sample {
+            encode_truncated_block_i32(samples, bits_per_sample, flags)
+        } else {
+            encode_delta_block_i32(samples[0], &deltas, delta_bits, flags)
+        };
+    }
+
+    let truncated_bits = signed_bit_width(samples);
+
+    // On equal sizes prefer the mode that is cheapest to decode
+    if bits_per_sample <= truncated_bits.min(delta_bits) {
+        encode_raw_block_i32(samples, bits_per_sample / 8, flags)
+    } else if truncated_bits <= delta_bits {
+        encode_truncated_block_i32(samples, truncated_bits, flags)
+    } else {
+        encode_delta_block_i32(samples[0], &deltas, delta_bits, flags)
+    }
+}
+
+/// Differences between consecutive samples. The final delta is never applied and is stored
+/// as zero.
+fn block_deltas(samples: &[i32]) -> Vec<i32> {
+    let mut deltas: Vec<i32> = samples
+        .windows(2)
+        .map(|w| w[1].wrapping_sub(w[0]))
+        .collect();
+    deltas.push(0);
+    deltas
+}
+
+fn encode_delta_block_i32(
+    base_value: i32,
+    deltas: &[i32],
+    bits: usize,
+    flags: BlockFlags,
+) -> (BlockHeader, Vec<u8>) {
+    let block_header = BlockHeader {
+        base_value,
+        bits: bits as i16,
+        flags,
+    };
+
+    (block_header, write_packed_values_i32(deltas, bits))
+}
+
+fn encode_truncated_block_i32(
+    samples: &[i32],
+    bits: usize,
+    flags: BlockFlags,
+) -> (BlockHeader, Vec<u8>) {
+    let block_header = BlockHeader {
+        base_value: samples[0],
+        bits: -(bits as i16),
+        flags,
+    };
+
+    (block_header, write_packed_values_i32(samples, bits))
+}
+
+fn encode_raw_block_i32(
+    samples: &[i32],
+    bytes_per_sample: usize,
+    flags: BlockFlags,
+) -> (BlockHeader, Vec<u8>) {
+    let block_header = BlockHeader {
+        base_value: samples[0],
+        bits: 0,
+        flags,
+    };
+
+    let mut data = Vec::with_capacity(samples.len() * bytes_per_sample);
+    for sample in samples {
+        data.extend_from_slice(&sample.to_le_bytes()[..bytes_per_sample]);
+    }
+
+    (block_header, data)
+}
+
+/// Smallest number of bits able to hold every value as a two's complement integer.
+fn signed_bit_width(values: &[i32]) -> usize {
+    values
+        .iter()
+        .map(|value| 33 - (value ^ (value >> 31)).leading_zeros() as usize)
+        .max()
+        .unwrap_or(1)
+}
+
+fn write_packed_values_i32(values: &[i32], precision_in_bits: usize) -> Vec<u8> {
+    let mut data: Vec<u8> = Vec::with_capacity(values.len() * precision_in_bits / 8);
+    let mut bit_accumulator: u64 = 0;
+    let mut bits_in_accumulator: usize = 0;
+    let mask = (1_u64 << precision_in_bits) - 1;
+
+    for value in values {
+        bit_accumulator |= (*value as u64 & mask) << bits_in_accumulator;
+        bits_in_accumulator += precision_in_bits;
+
+        // Flush whole bytes
+        while bits_in_accumulator >= 8 {
+            data.push(bit_accumulator as u8);
+            bit_accumulator >>= 8;
+            bits_in_accumulator -= 8;
+        }
+    }
+
+    if bits_in_accumulator > 0 {
+        data.push(bit_accumulator as u8);
+    }
+
+    data
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{read_wav_chunk, read_wav_data};
+    use crate::{ChannelEncoding, NcwReader, SampleFormat};
+    use std::{fs::File, io::Cursor};
+
+    /// Deterministic noise for tests, scaled to `bits` wide samples.
+    fn noise(len: usize, bits: u32) -> Vec<i32> {
+        let mut state: u32 = 0x12345678;
+        (0..len)
+            .map(|_| {
+                state = state.wrapping_mul(1664525).wrapping_add(1013904223);
+                state as i32 >> (32 - bits)
+            })
+            .collect()
+    }
+
+    fn roundtrip(channels: u16, bits_per_sample: u16, samples: &[i32]) -> Result<(), Error> {
+        let mut ncw = NcwWriter::new(Cursor::new(Vec::new()), channels, bits_per_sample, 44100)?;
+        ncw.write_samples(samples)?;
+
+        let mut reader = NcwReader::read(Cursor::new(ncw.writer.into_inner()))?;
+        assert_eq!(reader.header.channels, channels);
+        assert_eq!(reader.header.bits_per_sample, bits_per_sample);
+        assert_eq!(reader.header.sample_rate, 44100);
+        assert_eq!(reader.decode_samples()?, samples);
+        Ok(())
+    }
+
+    #[test]
+    fn test_signed_bit_width() {
+        assert_eq!(signed_bit_width(&[0]), 1);
+        assert_eq!(signed_bit_width(&[-1, 0]), 1);
+        assert_eq!(signed_bit_width(&[1]), 2);
+        assert_eq!(signed_bit_width(&[-2, 1]), 2);
+        assert_eq!(signed_bit_width(&[127, -128]), 8);
+        assert_eq!(signed_bit_width(&[128]), 9);
+        assert_eq!(signed_bit_width(&[i32::MIN, i32::MAX]), 32);
+    }
+
+    #[test]
+    fn test_write_fixtures() -> Result<(), Error> {
+        for name in [
+            "16-bit-mono",
+            "16-bit-stereo",
+            "24-bit-mono",
+            "24-bit-stereo",
+        ] {
+            let mut ncw = NcwReader::read(File::open(format!("tests/data/{name}.ncw"))?)?;
+            let samples = ncw.decode_samples()?;
+            roundtrip(ncw.header.channels, ncw.header.bits_per_sample, &samples)?;
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_write_noise() -> Result<(), Error> {
+        for bits_per_sample in [8, 16, 24, 32] {
+            for channels in [1, 2, 3] {
+                let samples = noise(1500 * channels as usize, bits_per_sample as u32);
+                roundtrip(channels, bits_per_sample, &samples)?;
+            }
+        }
+        roundtrip(1, 32, &[i32::MIN, i32::MAX, i32::MIN, 0, i32::MAX])?;
+        roundtrip(2, 16, &[])
+    }
+
+    #[test]
+    fn test_block_mode_selection() -> Result<(), Error> {
+        let ramp: Vec<i32> = (0..512).map(|i| i * 100 - 25600).collect();
+        let (block_header, data) =
+            encode_block_i32(&ramp, 16, BlockFlags::default(), Compression::Smallest);
+        assert_eq!(block_header.bits, 8);
+        assert_eq!(data.len(), 8 * 64);
+
+        let small_noise = noise(512, 4);
+        let (block_header, data) = encode_block_i32(
+            &small_noise,
+            16,
+            BlockFlags::default(),
+            Compression::Smallest,
+        );
+        assert_eq!(block_header.bits, -4);
+        assert_eq!(data.len(), 4 * 64);
+
+        let full_noise = noise(512, 24);
+        let (block_header, data) = encode_block_i32(
+            &full_noise,
+            24,
+            BlockFlags::default(),
+            Compression::Smallest,
+        );
+        assert_eq!(block_header.bits, 0);
+        assert_eq!(data.len(), 3 * 512);
+
+        // mixing all three modes in a single file
+        let mut samples = ramp;
+        samples.extend(small_noise);
+        samples.extend(full_noise.iter().map(|s| s >> 8));
+        roundtrip(1, 16, &samples)
+    }
+
+    /// Stereo blocks are never written as mid/side, even when the channels are correlated.
+    #[test]
+    fn test_write_stereo_left_right() -> Result<(), Error> {
+        let source = noise(1024, 14);
+        let samples: Vec<i32> = source
+            .iter()
+            .enumerate()
+            .flat_map(|(i, sample)| [sample * 2, sample * 2 + 2 * (i as i32 % 3)])
+            .collect();
+
+        let mut ncw = NcwWriter::new(Cursor::new(Vec::new()), 2, 16, 44100)?;
+        ncw.write_samples(&samples)?;
+
+        let mut reader = NcwReader::read(Cursor::new(ncw.writer.into_inner()))?;
+        assert_eq!(reader.decode_samples()?, samples);
+        for info in reader.block_infos() {
+            assert_eq!(info?.channel_encoding, ChannelEncoding::LeftRight);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_smaller_than_fixtures() -> Result<(), Error> {
+        for name in [
+            "16-bit-mono",
+            "16-bit-stereo",
+            "24-bit-stereo",
+            "24-bit-mono",
+        ] {
+            let original = std::fs::read(format!("tests/data/{name}.ncw"))?;
+            let mut reader = NcwReader::read(Cursor::new(&original))?;
+            let samples = reader.decode_samples()?;
+
+            let mut ncw = NcwWriter::new(
+                Cursor::new(Vec::new()),
+                reader.header.channels,
+                reader.header.bits_per_sample,
+                reader.header.sample_rate,
+            )?;
+            ncw.write_samples(&samples)?;
+
+            let encoded = ncw.writer.into_inner();
+            assert!(encoded.len() <= original.len(), "{name} is larger");
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_write_float() -> Result<(), Error> {
+        for name in ["32-bit-mono-float", "unknown-flag"] {
+            let original = std::fs::read(format!("tests/data/{name}.ncw"))?;
+            let mut reader = NcwReader::read(Cursor::new(&original))?;
+            let samples = reader.decode_samples_f32()?;
+
+            let mut ncw = NcwWriter::new(
+                Cursor::new(Vec::new()),
+                reader.header.channels,
+                32,
+                reader.header.sample_rate,
+            )?;
+            ncw.write_samples_f32(&samples)?;
+
+            let encoded = ncw.writer.into_inner();
+            assert!(encoded.len() <= original.len(), "{name} is larger");
+
+            let mut reader = NcwReader::read(Cursor::new(encoded))?;
+            assert_eq!(reader.header.sample_format(), SampleFormat::Float);
+            for info in reader.block_infos() {
+                let info = info?;
+                assert_eq!(info.sample_format, SampleFormat::Float);
+                assert_eq!(info.channel_encoding, ChannelEncoding::LeftRight);
+            }
+
+            let decoded = reader.decode_samples_f32()?;
+            assert_eq!(decoded.len(), samples.len());
+            assert!(decoded
+                .iter()
+                .zip(&samples)
+                .all(|(a, b)| a.to_bits() == b.to_bits()));
+        }
+
+        let special = [0.0, -0.0, 1.0, f32::MIN, f32::MAX, f32::INFINITY, f32::NAN];
+        let mut ncw = NcwWriter::new(Cursor::new(Vec::new()), 1, 32, 48000)?;
+        ncw.write_samples_f32(&special)?;
+        let mut reader = NcwReader::read(Cursor::new(ncw.writer.into_inner()))?;
+        let decoded: Vec<u32> = reader
+            .decode_samples_f32()?
+            .iter()
+            .map(|s| s.to_bits())
+            .collect();
+        assert_eq!(decoded, special.map(f32::to_bits));
+
+        let mut ncw = NcwWriter::new(Cursor::new(Vec::new()), 1, 24, 48000)?;
+        assert!(matches!(
+            ncw.write_samples_f32(&special),
+            Err(Error::UnsupportedBitDepth(24))
+        ));
+        Ok(())
+    }
+
+    /// Fixtures without a usable reference wav, with the reason. These are encoded from their own
+    /// decoded samples and header, so they only round trip NCW to NCW.
+    const NCW_SOURCED_FIXTURES: [(&str, &str); 2] = [
+        ("24-bit-stereo", "no reference wav is shipped"),
+        (
+            "testfile-onezero-16-bit-stereo",
+            "its wav's data chunk claims 525096 bytes but holds 220",
+        ),
+    ];
+
+    /// Encode a fixture's source audio with [`Compression::NativeInstruments`], taking the audio
+    /// and format from its reference wav unless it is listed in [`NCW_SOURCED_FIXTURES`].
+    fn encode_fixture(name: &str) -> Result<Vec<u8>, Error> {
+        let (mut ncw, samples, format) = if NCW_SOURCED_FIXTURES
+            .iter()
+            .any(|(fixture, _)| *fixture == name)
+        {
+            let file = File::open(format!("tests/data/{name}.ncw"))?;
+            let mut reader = NcwReader::read(file)?;
+            let ncw = NcwWriter::new(
+                Cursor::new(Vec::new()),
+                reader.header.channels,
+                reader.header.bits_per_sample,
+                reader.header.sample_rate,
+            )?;
+            (ncw, reader.decode_samples()?, reader.header.sample_format())
+        } else {
+            // Parsed by hand, as hound rejects the 20-byte fmt chunk of unknown-flag.wav
+            let path = format!("tests/data/{name}.wav");
+            let fmt = read_wav_chunk(&path, b"fmt ")?;
+            let field = |at: usize| u16::from_le_bytes([fmt[at], fmt[at + 1]]);
+            let (channels, sample_rate, bits_per_sample) = (
+                field(2),
+                u32::from_le_bytes(fmt[4..8].try_into().unwrap()),
+                field(14),
+            );
+            let format = match field(0) {
+                3 => SampleFormat::Float,
+                _ => SampleFormat::PCM,
+            };
+
+            let width = bits_per_sample as usize / 8;
+            let shift = 32 - bits_per_sample as u32;
+            let mut samples: Vec<i32> = read_wav_data(&path)?
+                .chunks_exact(width)
+                .map(|bytes| {
+                    let mut sample = [0; 4];
+                    sample[..width].copy_from_slice(bytes);
+                    (i32::from_le_bytes(sample) << shift) >> shift
+                })
+                .collect();
+
+            // 16-bit-mono.wav is padded with silence to a whole number of blocks, past the
+            // 474723 frames stored in the ncw
+            if name == "16-bit-mono" {
+                assert!(samples[474723..].iter().all(|s| *s == 0));
+                samples.truncate(474723);
+            }
+
+            let mut ncw = NcwWriter::new(
+                Cursor::new(Vec::new()),
+                channels,
+                bits_per_sample,
+                sample_rate,
+            )?;
+            // unknown-flag.ncw stores the name of the file it was encoded from in the reserved
+            // header bytes
+            if name == "unknown-flag" {
+                let file_name = "1_04skank_0_127_AB01.wav"
+                    .encode_utf16()
+                    .flat_map(u16::to_le_bytes);
+                for (byte, name_byte) in ncw.header.reserved.iter_mut().zip(file_name) {
+                    *byte = name_byte;
+                }
+            }
+            (ncw, samples, format)
+        };
+
+        ncw.compression = Compression::NativeInstruments;
+        match format {
+            SampleFormat::Float => {
+                let samples: Vec<f32> = samples.iter().map(|s| f32::from_bits(*s as u32)).collect();
+                ncw.write_samples_f32(&samples)?
+            }
+            SampleFormat::PCM => ncw.write_samples(&samples)?,
+        }
+        Ok(ncw.writer.into_inner())
+    }
+
+    #[test]
+    fn test_native_instruments_matches_fixtures() -> Result<(), Error> {
+        for name in [
+            "16-bit-mono",
+            "16-bit-stereo",
+            "24-bit-mono",
+            "24-bit-stereo",
+            "32-bit-mono-float",
+            "testfile-onezero-16-bit-stereo",
+            "testfile-onezero-16-bit-stereo-multiblock",
+            "unknown-flag",
+        ] {
+            let original = std::fs::read(format!("tests/data/{name}.ncw"))?;
+            assert!(encode_fixture(name)? == original, "{name} differs");
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_write_frames() -> Result<(), Error> {
+        let samples = noise(2 * 2148, 16);
+
+        let mut expected = NcwWriter::new(Cursor::new(Vec::new()), 2, 16, 44100)?;
+        expected.write_samples(&samples)?;
+        let expected = expected.writer.into_inner();
+
+        // Chunk sizes that straddle, match and span block boundaries
+        for chunk_len in [2, 2 * 100, 2 * 512, 2 * 1500, samples.len()] {
+            let mut ncw = NcwWriter::new(Cursor::new(Vec::new()), 2, 16, 44100)?;
+            for frames in samples.chunks(chunk_len) {
+                ncw.write_frames(frames)?;
+            }
+            ncw.finalize()?;
+
+            assert_eq!(ncw.header.num_samples, 2148);
+            assert!(
+                ncw.writer.into_inner() == expected,
+                "chunks of {chunk_len} differ"
+            );
+        }
+
+        // Nothing written still produces a valid empty file
+        let mut ncw = NcwWriter::new(Cursor::new(Vec::new()), 1, 16, 44100)?;
+        ncw.finalize()?;
+        let mut reader = NcwReader::read(Cursor::new(ncw.writer.into_inner()))?;
+        assert_eq!(reader.header.num_samples, 0);
+        assert!(reader.decode_samples()?.is_empty());
+
+        // Files can be streamed after other data in the same writer
+        let mut ncw = NcwWriter::new(Cursor::new(vec![0xAA; 7]), 2, 16, 44100)?;
+        ncw.writer.seek(SeekFrom::End(0))?;
+        ncw.write_frames(&samples)?;
+        ncw.finalize()?;
+        let data = ncw.writer.into_inner();
+        assert_eq!(data[..7], [0xAA; 7]);
+        assert!(data[7..] == expected);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_write_frames_invalid() -> Result<(), Error> {
+        let mut ncw = NcwWriter::new(Cursor::new(Vec::new()), 2, 16, 44100)?;
+        assert!(matches!(
+            ncw.write_frames(&[0; 3]),
+            Err(Error::InvalidSampleCount(3))
+        ));
+        assert!(matches!(
+            ncw.write_frames(&[0, 40000]),
+            Err(Error::SampleOutOfRange(40000))
+        ));
+        Ok(())
+    }
+
+    /// Lengths past what the header can describe fail before anything is written, rather than
+    /// truncating into a corrupt header. Too much input to pass for real, so the limits are
+    /// reached through the writer's state.
+    #[test]
+    fn test_write_too_large() -> Result<(), Error> {
+        let mut ncw = NcwWriter::new(Cursor::new(Vec::new()), 1, 16, 44100)?;
+        assert!(matches!(
+            ncw.write_blocks(u32::MAX as usize + 1, []),
+            Err(Error::FileTooLarge)
+        ));
+
+        let block = ncw.encoder(BlockFlags::default()).encode(&[0; 512]);
+        assert!(matches!(
+            ncw.write_block(u32::MAX - 10, block),
+            Err(Error::FileTooLarge)
+        ));
+        assert!(ncw.writer.get_ref().is_empty());
+
+        ncw.write_frames(&[0; 512])?;
+        ncw.header.num_samples = u32::MAX - 511;
+        assert!(matches!(
+            ncw.write_frames(&[0; 512]),
+            Err(Error::FileTooLarge)
+        ));
+
+        let mut ncw = NcwWriter::new(Cursor::new(Vec::new()), 1, 16, 44100)?;
+        ncw.write_frames(&[0; 512])?;
+        ncw.block_offsets.push(u32::MAX - 10);
+        assert!(matches!(
+            ncw.write_frames(&[0; 512]),
+            Err(Error::FileTooLarge)
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_write_frames_file() -> Result<(), Error> {
+        let samples = noise(2 * 3000, 16);
+        let path =
+            std::env::temp_dir().join(format!("ncw-write-frames-{}.ncw", std::process::id()));
+
+        // finalize reads the data back, so a write-only file fails before any audio is written
+        let mut ncw = NcwWriter::new(File::create(&path)?, 2, 16, 44100)?;
+        assert!(matches!(ncw.write_frames(&samples), Err(Error::IoError(_))));
+
+        let file = std::fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .truncate(true)
+            .open(&path)?;
+        let mut ncw = NcwWriter::new(file, 2, 16, 44100)?;
+        ncw.write_frames(&samples)?;
+        ncw.finalize()?;
+
+        let decoded = NcwReader::read(File::open(&path)?)?.decode_samples();
+        std::fs::remove_file(&path)?;
+        assert_eq!(decoded?, samples);
+        Ok(())
+    }
+
+    #[cfg(feature = "rayon")]
+    #[test]
+    fn test_write_samples_parallel() -> Result<(), Error> {
+        for (channels, bits_per_sample, compression) in [
+            (1, 16, Compression::Smallest),
+            (2, 16, Compression::Smallest),
+            (2, 24, Compression::NativeInstruments),
+            (3, 8, Compression::Smallest),
+        ] {
+            let samples = noise(channels as usize * 5000, bits_per_sample as u32 - 2);
+            let encode = |parallel: bool| -> Result<Vec<u8>, Error> {
+                let mut ncw =
+                    NcwWriter::new(Cursor::new(Vec::new()), channels, bits_per_sample, 44100)?;
+                ncw.compression = compression;
+                if parallel {
+                    ncw.write_samples_parallel(&samples)?;
+                } else {
+                    ncw.write_samples(&samples)?;
+                }
+                Ok(ncw.writer.into_inner())
+            };
+            assert!(encode(true)? == encode(false)?);
+        }
+
+        let samples: Vec<f32> = noise(3000, 24)
+            .iter()
+            .map(|s| *s as f32 / 8388608.0)
+            .collect();
+        let encode = |parallel: bool| -> Result<Vec<u8>, Error> {
+            let mut ncw = NcwWriter::new(Cursor::new(Vec::new()), 2, 32, 48000)?;
+            if parallel {
+                ncw.write_samples_f32_parallel(&samples)?;
+            } else {
+                ncw.write_samples_f32(&samples)?;
+            }
+            Ok(ncw.writer.into_inner())
+        };
+        assert!(encode(true)? == encode(false)?);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_write_invalid() {
+        let writer = || Cursor::new(Vec::new());
+        assert!(matches!(
+            NcwWriter::new(writer(), 0, 16, 44100),
+            Err(Error::InvalidChannelCount(0))
+        ));
+        assert!(matches!(
+            NcwWriter::new(writer(), 1, 12, 44100),
+            Err(Error::UnsupportedBitDepth(12))
+        ));
+
+        let mut ncw = NcwWriter::new(writer(), 2, 16, 44100).unwrap();
+        assert!(matches!(
+            ncw.write_samples(&[0, 1, 2]),
+            Err(Error::InvalidSampleCount(3))
+        ));
+        assert!(matches!(
+            ncw.write_samples(&[0, 32768]),
+            Err(Error::SampleOutOfRange(32768))
+        ));
+    }
+}